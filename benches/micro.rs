@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::micro::{IRG_MASKS, MEM_SIZE};
+use mte_measurement::region::Region;
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use mte_measurement::variant::{Micro, MICRO};
+use mte_measurement::{set_mte_mode, set_mte_mode_tags, set_tags_random, MTEMode};
+
+/// Runs one round of `micro` per criterion iteration
+fn measure_custom(iters: u64, micro: &Micro, mem: *mut u8) -> std::time::Duration {
+    let start = std::time::Instant::now();
+    unsafe { (micro.run)(mem, iters) };
+    start.elapsed()
+}
+
+pub fn criterion_benchmark_micro(c: &mut Criterion) {
+    unsafe {
+        set_mte_mode(MTEMode::Sync);
+    }
+
+    let cooldown = Cooldown::new(CooldownConfig::default());
+    let mut region = Region::map(MEM_SIZE, 0).expect("could not map memory");
+    unsafe { set_tags_random(region.as_mut_slice()) };
+    let mem = region.as_ptr();
+
+    for cluster in discover_clusters() {
+        pin_to_cpu(cluster.representative()).expect("could not pin to cpu");
+
+        let mut group = c.benchmark_group(cluster.label());
+        for micro in MICRO {
+            group.throughput(Throughput::Elements(micro.ops));
+            if micro.instruction == "irg" {
+                for (mask_name, mask) in IRG_MASKS {
+                    unsafe { set_mte_mode_tags(MTEMode::Sync, mask) };
+                    cooldown.wait();
+                    group.bench_function(format!("{}/{}", micro.name(), mask_name), |b| {
+                        b.iter_custom(|iters| measure_custom(iters, micro, mem))
+                    });
+                }
+                unsafe { set_mte_mode(MTEMode::Sync) };
+            } else {
+                cooldown.wait();
+                group.bench_function(micro.name(), |b| {
+                    b.iter_custom(|iters| measure_custom(iters, micro, mem))
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, criterion_benchmark_micro);
+criterion_main!(benches);
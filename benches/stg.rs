@@ -1,20 +1,34 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use mte_measurement::{
-    memset, set_mte_mode, st2g, st2g_zero, stg, stg_zero, stgp, stz2g, stzg, MTEMode,
-};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use mte_measurement::variant::{supported, Variant};
+use mte_measurement::{set_mte_mode, MTEMode};
 use rand::random;
 
-// 128 MiB
-const SIZE: usize = 128 * 1024 * 1024;
+const SIZES: [usize; 5] = [4 << 10, 64 << 10, 1 << 20, 16 << 20, 128 << 20];
 
-fn measure_custom(iters: u64, f: impl Fn(&mut [u8]) -> ()) -> std::time::Duration {
+const MODES: [(&str, MTEMode); 3] = [
+    ("none", MTEMode::None),
+    ("sync", MTEMode::Sync),
+    ("async", MTEMode::Async),
+];
+
+/// Set to skip checking the memory after every iteration
+const NO_VALIDATE: &str = "MTE_NO_VALIDATE";
+
+fn measure_custom(
+    iters: u64,
+    size: usize,
+    variant: &Variant,
+    validate: bool,
+) -> std::time::Duration {
     let mut result = std::time::Duration::from_secs(0);
 
     for _ in 0..iters {
         let mem = unsafe {
             libc::mmap(
                 std::ptr::null_mut(),
-                SIZE,
+                size,
                 libc::PROT_READ | libc::PROT_WRITE | 0x20,
                 libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
                 -1,
@@ -23,75 +37,52 @@ fn measure_custom(iters: u64, f: impl Fn(&mut [u8]) -> ()) -> std::time::Duratio
         };
 
         assert_ne!(mem, libc::MAP_FAILED);
-        let mut mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
+        let mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, size) };
 
+        let tag = random();
         let start = std::time::Instant::now();
-        f(&mut mem_slice);
+        unsafe { (variant.run)(black_box(&mut *mem_slice), black_box(tag)) };
         result += start.elapsed();
 
-        unsafe { libc::munmap(mem, SIZE) };
+        if validate {
+            if let Err(e) = unsafe { variant.validate(mem_slice, tag) } {
+                panic!("validation failed: {}", e);
+            }
+        }
+
+        unsafe { libc::munmap(mem, size) };
     }
 
     result
 }
 
+/// One group per cluster and variant, with a benchmark per mode and size
 pub fn criterion_benchmark_stg(c: &mut Criterion) {
-    unsafe {
-        set_mte_mode(MTEMode::Sync);
-    }
+    let cooldown = Cooldown::new(CooldownConfig::default());
+    let validate = std::env::var_os(NO_VALIDATE).is_none();
 
-    c.bench_function("memset", |b| {
-        b.iter_custom(|iters| measure_custom(iters, |mem| unsafe { memset(black_box(mem)) }))
-    });
-    c.bench_function("stg", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                stg(black_box(mem), black_box(random()))
-            })
-        })
-    });
-    c.bench_function("stgp", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                stgp(black_box(mem), black_box(random()))
-            })
-        })
-    });
-    c.bench_function("st2g", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                st2g(black_box(mem), black_box(random()))
-            })
-        })
-    });
-    c.bench_function("stzg", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                stzg(black_box(mem), black_box(random()))
-            })
-        })
-    });
-    c.bench_function("stz2g", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                stz2g(black_box(mem), black_box(random()))
-            })
-        })
-    });
-    c.bench_function("stg+memset", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                stg_zero(black_box(mem), black_box(random()))
-            })
-        })
-    });
-    c.bench_function("st2g+memset", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, |mem| unsafe {
-                st2g_zero(black_box(mem), black_box(random()))
-            })
-        })
-    });
+    for cluster in discover_clusters() {
+        pin_to_cpu(cluster.representative()).expect("could not pin to cpu");
+
+        for variant in supported() {
+            let mut group = c.benchmark_group(format!("{}/{}", cluster.label(), variant.name));
+
+            for (name, mode) in MODES {
+                unsafe {
+                    set_mte_mode(mode);
+                }
+
+                for size in SIZES {
+                    group.throughput(Throughput::Bytes(size as u64));
+                    cooldown.wait();
+                    group.bench_with_input(BenchmarkId::new(name, size), &size, |b, &size| {
+                        b.iter_custom(|iters| measure_custom(iters, size, variant, validate))
+                    });
+                }
+            }
+            group.finish();
+        }
+    }
 }
 
 criterion_group!(benches, criterion_benchmark_stg);
@@ -1,10 +1,17 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use mte_measurement::{memset, set_mte_mode, MTEMode};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::load::Workload;
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use mte_measurement::{set_mte_mode, MTEMode};
 
-// 128 MiB
-const SIZE: usize = 128 * 1024 * 1024;
+const SIZES: [usize; 5] = [4 << 10, 64 << 10, 1 << 20, 16 << 20, 128 << 20];
 
-fn measure_custom(iters: u64, mode: MTEMode, f: impl Fn(&mut [u8]) -> ()) -> std::time::Duration {
+fn measure_custom(
+    iters: u64,
+    size: usize,
+    mode: MTEMode,
+    workload: Workload,
+) -> std::time::Duration {
     let mut result = std::time::Duration::from_secs(0);
 
     unsafe {
@@ -15,7 +22,7 @@ fn measure_custom(iters: u64, mode: MTEMode, f: impl Fn(&mut [u8]) -> ()) -> std
         let mem = unsafe {
             libc::mmap(
                 std::ptr::null_mut(),
-                SIZE,
+                size,
                 libc::PROT_READ | libc::PROT_WRITE | 0x20,
                 libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
                 -1,
@@ -24,40 +31,46 @@ fn measure_custom(iters: u64, mode: MTEMode, f: impl Fn(&mut [u8]) -> ()) -> std
         };
 
         assert_ne!(mem, libc::MAP_FAILED);
-        let mut mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
+        let mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, size) };
+        let tag = unsafe { workload.prepare(mem_slice) };
 
         let start = std::time::Instant::now();
-        f(&mut mem_slice);
+        unsafe { workload.run(black_box(mem_slice), black_box(tag)) };
         result += start.elapsed();
 
-        unsafe { libc::munmap(mem, SIZE) };
+        unsafe { libc::munmap(mem, size) };
     }
 
     result
 }
 
+/// One group per cluster and workload, with a benchmark per mode and size
 pub fn criterion_benchmark_stg(c: &mut Criterion) {
-    c.bench_function("none", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, MTEMode::None, |mem| unsafe {
-                memset(black_box(mem))
-            })
-        })
-    });
-    c.bench_function("sync", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, MTEMode::Sync, |mem| unsafe {
-                memset(black_box(mem))
-            })
-        })
-    });
-    c.bench_function("async", |b| {
-        b.iter_custom(|iters| {
-            measure_custom(iters, MTEMode::Async, |mem| unsafe {
-                memset(black_box(mem))
-            })
-        })
-    });
+    let cooldown = Cooldown::new(CooldownConfig::default());
+    let modes = [
+        ("none", MTEMode::None),
+        ("sync", MTEMode::Sync),
+        ("async", MTEMode::Async),
+    ];
+
+    for cluster in discover_clusters() {
+        pin_to_cpu(cluster.representative()).expect("could not pin to cpu");
+
+        for workload in Workload::ALL {
+            let mut group = c.benchmark_group(format!("{}/{}", cluster.label(), workload.name()));
+
+            for (name, mode) in modes {
+                for size in SIZES {
+                    group.throughput(Throughput::Bytes(size as u64));
+                    cooldown.wait();
+                    group.bench_with_input(BenchmarkId::new(name, size), &size, |b, &size| {
+                        b.iter_custom(|iters| measure_custom(iters, size, mode, workload))
+                    });
+                }
+            }
+            group.finish();
+        }
+    }
 }
 
 criterion_group!(benches, criterion_benchmark_stg);
@@ -0,0 +1,216 @@
+use clap::{Parser, ValueEnum};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::fault::{faults, install_handler, last_fault, now_ns};
+use mte_measurement::perf::{Counter, CounterGroup, Counters};
+use mte_measurement::region::Region;
+use mte_measurement::{set_mte_mode, set_tag, stg, MTEMode};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// What the thread does after the mismatched store until the fault is reported
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Activity {
+    /// Arithmetic without entering the kernel
+    Compute,
+    /// Back to back `getppid` system calls
+    Syscall,
+    /// Repeated `nanosleep` of `--sleep-us`
+    Sleep,
+}
+
+impl Activity {
+    const ALL: [Activity; 3] = [Activity::Compute, Activity::Syscall, Activity::Sleep];
+
+    fn name(self) -> &'static str {
+        match self {
+            Activity::Compute => "compute",
+            Activity::Syscall => "syscall",
+            Activity::Sleep => "sleep",
+        }
+    }
+
+    /// Runs until a fault has been handled since `before` or `timeout` has passed.
+    ///
+    /// Returns the number of system calls made, or `None` on timeout.
+    fn until_fault(self, before: u64, timeout: Duration, sleep: Duration) -> Option<u64> {
+        let start = Instant::now();
+        let mut syscalls = 0;
+        let mut x = 1u64;
+
+        // Reading the clock goes through the vDSO and does not enter the kernel
+        while faults() == before {
+            if start.elapsed() > timeout {
+                return None;
+            }
+            match self {
+                Activity::Compute => {
+                    for _ in 0..1000 {
+                        x = black_box(x.wrapping_mul(6364136223846793005).wrapping_add(1));
+                    }
+                }
+                Activity::Syscall => {
+                    unsafe { libc::syscall(libc::SYS_getppid) };
+                    syscalls += 1;
+                }
+                Activity::Sleep => {
+                    std::thread::sleep(sleep);
+                    syscalls += 1;
+                }
+            }
+        }
+
+        Some(syscalls)
+    }
+}
+
+#[derive(Parser)]
+struct Args {
+    /// Activities to run after the mismatched store, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = Activity::ALL)]
+    activity: Vec<Activity>,
+    /// Faults to trigger per activity
+    #[arg(long, default_value_t = 100)]
+    iters: usize,
+    /// Give up on a fault after this many milliseconds
+    #[arg(long, default_value_t = 1000)]
+    timeout_ms: u64,
+    /// Duration of each sleep of the `sleep` activity in microseconds
+    #[arg(long, default_value_t = 100)]
+    sleep_us: u64,
+}
+
+/// One fault from the mismatched store until the thread noticed the signal
+struct Sample {
+    latency: Duration,
+    instructions: Option<u64>,
+    syscalls: u64,
+}
+
+fn instructions(counters: &Counters) -> Option<u64> {
+    counters
+        .read()
+        .into_iter()
+        .find(|value| value.counter == Counter::Instructions)
+        .map(|value| value.value)
+}
+
+/// Performs a store with a mismatched tag to `mem` and runs `activity` until the fault arrives
+fn measure(
+    mem: &mut [u8],
+    tag: u64,
+    activity: Activity,
+    counters: &Counters,
+    args: &Args,
+) -> Option<Sample> {
+    let retired = instructions(counters);
+    let wrong = set_tag(mem.as_mut_ptr(), tag ^ (1 << 56));
+    let before = faults();
+
+    counters.enable();
+    let start = now_ns();
+    unsafe { wrong.write_volatile(1) };
+    let syscalls = activity.until_fault(
+        before,
+        Duration::from_millis(args.timeout_ms),
+        Duration::from_micros(args.sleep_us),
+    );
+    counters.disable();
+
+    match syscalls {
+        Some(syscalls) => Some(Sample {
+            latency: Duration::from_nanos(last_fault().saturating_sub(start)),
+            instructions: instructions(counters)
+                .zip(retired)
+                .map(|(after, before)| after - before),
+            syscalls,
+        }),
+        None => {
+            // Enter the kernel so the fault is reported before the next sample
+            unsafe { libc::syscall(libc::SYS_getppid) };
+            None
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    install_handler().expect("could not install signal handler");
+    unsafe {
+        set_mte_mode(MTEMode::Async);
+    }
+
+    let mut region = Region::map(4096, 0).expect("could not map memory");
+    let tag = 3 << 56;
+    unsafe { stg(region.as_mut_slice(), tag) };
+
+    for cluster in discover_clusters() {
+        let cpu = cluster.representative();
+        pin_to_cpu(cpu).expect("could not pin to cpu");
+        let counters = Counters::open(&[CounterGroup(vec![Counter::Instructions])], Some(cpu));
+
+        let mut table = format!(
+            "{:10} {:>12} {:>12} {:>14} {:>10} {:>10}",
+            "activity", "mean", "max", "instructions", "syscalls", "timeouts"
+        );
+
+        for &activity in &args.activity {
+            let results = (0..args.iters)
+                .map(|_| measure(region.as_mut_slice(), tag, activity, &counters, &args))
+                .collect::<Vec<_>>();
+            let samples = results.iter().flatten().collect::<Vec<_>>();
+            let timeouts = results.len() - samples.len();
+
+            if samples.is_empty() {
+                println!(
+                    "{} {}: no fault within {} ms",
+                    cluster,
+                    activity.name(),
+                    args.timeout_ms
+                );
+                table += &format!(
+                    "\n{:10} {:>12} {:>12} {:>14} {:>10} {:>10}",
+                    activity.name(),
+                    "-",
+                    "-",
+                    "-",
+                    "-",
+                    timeouts
+                );
+                continue;
+            }
+
+            let n = samples.len() as u64;
+            let mean = samples.iter().map(|s| s.latency).sum::<Duration>() / n as u32;
+            let max = samples.iter().map(|s| s.latency).max().unwrap_or_default();
+            let instructions = samples
+                .iter()
+                .map(|s| s.instructions)
+                .sum::<Option<u64>>()
+                .map_or_else(|| "-".to_string(), |total| (total / n).to_string());
+            let syscalls = samples.iter().map(|s| s.syscalls).sum::<u64>() as f64 / n as f64;
+
+            println!(
+                "{} {}: {:.2?} mean, {:.2?} max, {} instructions, {:.1} syscalls, {} timeouts",
+                cluster,
+                activity.name(),
+                mean,
+                max,
+                instructions,
+                syscalls,
+                timeouts
+            );
+            table += &format!(
+                "\n{:10} {:>12} {:>12} {:>14} {:>10.1} {:>10}",
+                activity.name(),
+                format!("{:.2?}", mean),
+                format!("{:.2?}", max),
+                instructions,
+                syscalls,
+                timeouts
+            );
+        }
+
+        println!("{} async fault latency:\n{}", cluster, table);
+    }
+}
@@ -0,0 +1,410 @@
+use std::arch::asm;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use rand::{random, thread_rng, Rng};
+
+use crate::{load_tag, set_tag};
+
+const GRANULE: usize = 16;
+
+/// Distribution the sizes of allocations are drawn from
+#[derive(Clone, Debug)]
+pub enum SizeDistribution {
+    /// Every allocation has the same size
+    Fixed(usize),
+    /// Sizes uniformly distributed between two bounds (inclusive)
+    Uniform(usize, usize),
+    /// Sizes with relative weights, e.g. a histogram recorded from a real program
+    Weighted(Vec<(usize, f64)>),
+}
+
+impl SizeDistribution {
+    /// Reads a histogram with one `size weight` pair per line, ignoring empty lines and `#` comments
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+
+        let weights = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                let size = fields.next().and_then(|s| s.parse().ok());
+                let weight = fields.next().map_or(Some(1.0), |w| w.parse().ok());
+                match (size, weight) {
+                    (Some(size), Some(weight)) => Ok((size, weight)),
+                    _ => Err(format!("invalid line in {}: `{}`", path, line)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if weights.iter().map(|&(_, w)| w).sum::<f64>() <= 0.0 {
+            return Err(format!("{} contains no sizes", path));
+        }
+        Ok(SizeDistribution::Weighted(weights))
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        let size = match self {
+            SizeDistribution::Fixed(size) => *size,
+            SizeDistribution::Uniform(min, max) => rng.gen_range(*min..=*max),
+            SizeDistribution::Weighted(weights) => {
+                let total = weights.iter().map(|&(_, w)| w).sum::<f64>();
+                let mut target = rng.gen_range(0.0..total);
+                weights
+                    .iter()
+                    .find(|&&(_, w)| {
+                        target -= w;
+                        target < 0.0
+                    })
+                    .or(weights.last())
+                    .map_or(GRANULE, |&(size, _)| size)
+            }
+        };
+        size.max(1)
+    }
+}
+
+impl FromStr for SizeDistribution {
+    type Err = String;
+
+    /// Parses `fixed:<size>`, `uniform:<min>-<max>` or `file:<path>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("invalid size `{}`: {}", n, e))
+        };
+
+        match s.split_once(':') {
+            Some(("fixed", size)) => Ok(SizeDistribution::Fixed(parse(size)?)),
+            Some(("uniform", range)) => {
+                let (min, max) = range
+                    .split_once('-')
+                    .ok_or_else(|| format!("expected uniform:<min>-<max>, got `{}`", s))?;
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min > max {
+                    return Err(format!("empty range `{}`", range));
+                }
+                Ok(SizeDistribution::Uniform(min, max))
+            }
+            Some(("file", path)) => SizeDistribution::read(path),
+            _ => Err(format!(
+                "expected fixed:<size>, uniform:<min>-<max> or file:<path>, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeDistribution::Fixed(size) => write!(f, "fixed:{}", size),
+            SizeDistribution::Uniform(min, max) => write!(f, "uniform:{}-{}", min, max),
+            SizeDistribution::Weighted(weights) => write!(f, "{} weighted sizes", weights.len()),
+        }
+    }
+}
+
+/// One allocation within a region
+#[derive(Copy, Clone, Debug)]
+pub struct Chunk {
+    /// Offset from the start of the region, a multiple of 16 bytes
+    pub offset: usize,
+    /// Requested size in bytes
+    pub size: usize,
+    pub tag: u64,
+}
+
+impl Chunk {
+    /// Bytes covered by the tags of this chunk, the size rounded up to whole granules
+    pub fn tagged_size(&self) -> usize {
+        self.size.next_multiple_of(GRANULE)
+    }
+}
+
+/// Draws `count` allocations from `sizes` with random tags and lays them out back to back.
+///
+/// Returns the chunks and the number of bytes they span.
+pub fn layout(sizes: &SizeDistribution, count: usize) -> (Vec<Chunk>, usize) {
+    let mut rng = thread_rng();
+    let mut offset = 0;
+
+    let chunks = (0..count)
+        .map(|_| {
+            let chunk = Chunk {
+                offset,
+                size: sizes.sample(&mut rng),
+                tag: random::<u64>() & 0x0f00_0000_0000_0000,
+            };
+            offset += chunk.tagged_size();
+            chunk
+        })
+        .collect();
+
+    (chunks, offset)
+}
+
+/// How an allocator tags (and zeroes) a chunk
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChunkVariant {
+    /// Zero the chunk without tagging it, the baseline
+    Memset,
+    Stg,
+    /// `st2g` for pairs of granules, with `stg` for an unaligned head or an odd tail granule
+    St2g,
+    Stzg,
+    /// `stz2g` for pairs of granules, with `stzg` for an unaligned head or an odd tail granule
+    Stz2g,
+    Stgp,
+}
+
+impl ChunkVariant {
+    pub const ALL: [ChunkVariant; 6] = [
+        ChunkVariant::Memset,
+        ChunkVariant::Stg,
+        ChunkVariant::St2g,
+        ChunkVariant::Stzg,
+        ChunkVariant::Stz2g,
+        ChunkVariant::Stgp,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChunkVariant::Memset => "memset",
+            ChunkVariant::Stg => "stg",
+            ChunkVariant::St2g => "st2g",
+            ChunkVariant::Stzg => "stzg",
+            ChunkVariant::Stz2g => "stz2g",
+            ChunkVariant::Stgp => "stgp",
+        }
+    }
+
+    /// Whether tagging also zeroes the chunk
+    pub fn zeroes(self) -> bool {
+        matches!(
+            self,
+            ChunkVariant::Memset | ChunkVariant::Stzg | ChunkVariant::Stz2g | ChunkVariant::Stgp
+        )
+    }
+
+    /// Tags `chunk` within `mem` and, if `zero` is set and tagging does not zero it, clears its
+    /// requested size with `memset` through the tagged pointer.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be mapped with `PROT_MTE`, aligned to 16 bytes and contain the chunk's tagged size.
+    pub unsafe fn allocate(self, mem: &mut [u8], chunk: &Chunk, zero: bool) {
+        let start = set_tag(mem.as_mut_ptr().add(chunk.offset), chunk.tag);
+        let end = start.add(chunk.tagged_size());
+
+        match self {
+            ChunkVariant::Memset => {
+                mem[chunk.offset..chunk.offset + chunk.size].fill(0);
+                return;
+            }
+            ChunkVariant::Stg => granules(start, end, |p| stg(p)),
+            ChunkVariant::Stzg => granules(start, end, |p| stzg(p)),
+            ChunkVariant::Stgp => granules(start, end, |p| stgp(p)),
+            ChunkVariant::St2g => pairs(start, end, |p| stg(p), |p| st2g(p)),
+            ChunkVariant::Stz2g => pairs(start, end, |p| stzg(p), |p| stz2g(p)),
+        }
+
+        if zero && !self.zeroes() {
+            std::slice::from_raw_parts_mut(start, chunk.size).fill(0);
+        }
+    }
+
+    /// Checks that `chunk` within `mem` holds what [`ChunkVariant::allocate`] leaves behind: the
+    /// chunk's tag in every granule (or the untouched tag 0 for `memset`) and zeros in its
+    /// requested size if `zero` is set or the variant zeroes.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be mapped with `PROT_MTE`, aligned to 16 bytes and contain the chunk's tagged size.
+    pub unsafe fn validate(self, mem: &[u8], chunk: &Chunk, zero: bool) -> Result<(), String> {
+        let expected = match self {
+            ChunkVariant::Memset => 0,
+            _ => chunk.tag,
+        };
+
+        for offset in (chunk.offset..chunk.offset + chunk.tagged_size()).step_by(GRANULE) {
+            let actual = load_tag(mem.as_ptr().add(offset));
+            if actual != expected {
+                return Err(format!(
+                    "{} left tag {:#x} instead of {:#x} at offset {}",
+                    self.name(),
+                    actual >> 56,
+                    expected >> 56,
+                    offset
+                ));
+            }
+        }
+
+        let start = set_tag(mem.as_ptr().add(chunk.offset) as *mut u8, expected);
+        let data = std::slice::from_raw_parts(start, chunk.size);
+        if (zero || self.zeroes()) && data.iter().any(|&byte| byte != 0) {
+            return Err(format!(
+                "{} did not zero the {} byte chunk at offset {}",
+                self.name(),
+                chunk.size,
+                chunk.offset
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+unsafe fn granules(mut p: *mut u8, end: *mut u8, single: impl Fn(*mut u8)) {
+    while p < end {
+        single(p);
+        p = p.add(GRANULE);
+    }
+}
+
+/// Tags pairs of granules, starting with a single granule if `p` is not 32-byte aligned and
+/// ending with one if a single granule is left
+unsafe fn pairs(mut p: *mut u8, end: *mut u8, single: impl Fn(*mut u8), pair: impl Fn(*mut u8)) {
+    if p < end && !(p as usize).is_multiple_of(2 * GRANULE) {
+        single(p);
+        p = p.add(GRANULE);
+    }
+    while p.add(2 * GRANULE) <= end {
+        pair(p);
+        p = p.add(2 * GRANULE);
+    }
+    if p < end {
+        single(p);
+    }
+}
+
+// The tag is taken from the (tagged) address itself
+
+#[inline(always)]
+unsafe fn stg(p: *mut u8) {
+    asm!("stg {p}, [{p}]", p = in(reg) p, options(nostack));
+}
+
+#[inline(always)]
+unsafe fn stzg(p: *mut u8) {
+    asm!("stzg {p}, [{p}]", p = in(reg) p, options(nostack));
+}
+
+#[inline(always)]
+unsafe fn st2g(p: *mut u8) {
+    asm!("st2g {p}, [{p}]", p = in(reg) p, options(nostack));
+}
+
+#[inline(always)]
+unsafe fn stz2g(p: *mut u8) {
+    asm!("stz2g {p}, [{p}]", p = in(reg) p, options(nostack));
+}
+
+#[inline(always)]
+unsafe fn stgp(p: *mut u8) {
+    asm!("stgp xzr, xzr, [{p}]", p = in(reg) p, options(nostack));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `text` as a size histogram read from a file
+    fn read(text: &str) -> Result<SizeDistribution, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sizes.txt");
+        fs::write(&path, text).unwrap();
+        format!("file:{}", path.display()).parse()
+    }
+
+    #[test]
+    fn parses_distributions() {
+        let fixed = "fixed:64".parse::<SizeDistribution>().unwrap();
+        assert!(matches!(fixed, SizeDistribution::Fixed(64)));
+        let uniform = "uniform:16-4096".parse::<SizeDistribution>().unwrap();
+        assert!(matches!(uniform, SizeDistribution::Uniform(16, 4096)));
+        assert_eq!(uniform.to_string(), "uniform:16-4096");
+        let single = "uniform: 32 - 32".parse::<SizeDistribution>().unwrap();
+        assert!(matches!(single, SizeDistribution::Uniform(32, 32)));
+    }
+
+    #[test]
+    fn rejects_invalid_distributions() {
+        for invalid in [
+            "uniform:4096-16",
+            "uniform:16",
+            "uniform:a-16",
+            "fixed:-1",
+            "fixed:",
+            "normal:16",
+            "64",
+            "file:/no/such/file",
+        ] {
+            assert!(
+                invalid.parse::<SizeDistribution>().is_err(),
+                "`{}` was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn reads_weighted_sizes() {
+        let sizes = read("# size weight\n16 3.5\n\n32  # weight defaults to 1\n  48 0\n").unwrap();
+        let SizeDistribution::Weighted(weights) = &sizes else {
+            panic!("expected weighted sizes, got {:?}", sizes);
+        };
+        assert_eq!(weights, &[(16, 3.5), (32, 1.0), (48, 0.0)]);
+        assert_eq!(sizes.to_string(), "3 weighted sizes");
+
+        // Sizes without weight are never drawn
+        let mut rng = thread_rng();
+        assert!((0..100).all(|_| sizes.sample(&mut rng) != 48));
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let err = read("16 1\n16 x\n").unwrap_err();
+        assert!(
+            err.contains("invalid line") && err.contains("`16 x`"),
+            "{}",
+            err
+        );
+        assert!(read("size 1\n").unwrap_err().contains("invalid line"));
+        assert!(read("# only a comment\n").unwrap_err().contains("no sizes"));
+        assert!(read("16 0\n32 0\n").unwrap_err().contains("no sizes"));
+    }
+
+    #[test]
+    fn layout_packs_aligned_chunks() {
+        let (chunks, size) = layout(&SizeDistribution::Uniform(1, 100), 1000);
+        assert_eq!(chunks.len(), 1000);
+        assert_eq!(size, chunks.iter().map(Chunk::tagged_size).sum::<usize>());
+
+        let mut end = 0;
+        for chunk in &chunks {
+            assert!((1..=100).contains(&chunk.size), "{:?}", chunk);
+            assert_eq!(chunk.offset % GRANULE, 0, "{:?}", chunk);
+            assert_eq!(chunk.offset, end, "{:?} overlaps or leaves a gap", chunk);
+            assert!(chunk.tagged_size() >= chunk.size);
+            assert_eq!(chunk.tag & !0x0f00_0000_0000_0000, 0, "{:?}", chunk);
+            end = chunk.offset + chunk.tagged_size();
+        }
+        assert_eq!(end, size);
+    }
+
+    #[test]
+    fn fixed_layout_rounds_up_to_granules() {
+        let (chunks, size) = layout(&SizeDistribution::Fixed(24), 4);
+        let offsets = chunks.iter().map(|c| c.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, [0, 32, 64, 96]);
+        assert_eq!(size, 128);
+        assert!(layout(&SizeDistribution::Fixed(24), 0).0.is_empty());
+    }
+}
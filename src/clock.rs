@@ -0,0 +1,261 @@
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+/// How long each calibration round compares a clock against [`Instant`]
+const CALIBRATION_TIME: Duration = Duration::from_millis(50);
+const CALIBRATION_ROUNDS: usize = 5;
+
+/// A monotonic time source that counts in ticks
+pub trait Clock: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Current value of the counter
+    fn now(&self) -> u64;
+
+    /// Ticks per second
+    fn frequency(&self) -> f64;
+
+    fn to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_secs_f64(ticks as f64 / self.frequency())
+    }
+}
+
+/// Which [`Clock`] the harness uses for timing
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ClockKind {
+    /// The cycle counter of the current architecture, `instant` if there is none
+    #[default]
+    Auto,
+    /// `std::time::Instant`, i.e. `clock_gettime(CLOCK_MONOTONIC)`
+    Instant,
+    /// The aarch64 virtual counter `cntvct_el0`
+    Cntvct,
+    /// The x86 time stamp counter
+    Tsc,
+}
+
+impl ClockKind {
+    /// Creates the clock, calibrating its frequency if necessary
+    pub fn create(self) -> Result<Box<dyn Clock>, String> {
+        match self {
+            ClockKind::Instant => Ok(Box::new(InstantClock::new())),
+            #[cfg(target_arch = "aarch64")]
+            ClockKind::Auto | ClockKind::Cntvct => Ok(Box::new(VirtualCounter::new())),
+            #[cfg(target_arch = "x86_64")]
+            ClockKind::Auto | ClockKind::Tsc => Ok(Box::new(Tsc::new())),
+            #[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+            ClockKind::Auto => Ok(Box::new(InstantClock::new())),
+            #[allow(unreachable_patterns)]
+            kind => Err(format!("clock {:?} is not available on this architecture", kind)),
+        }
+    }
+}
+
+/// Measures the frequency of `now` against [`Instant`], taking the median of several rounds
+fn calibrate(now: impl Fn() -> u64) -> f64 {
+    let mut rounds = (0..CALIBRATION_ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            let start_ticks = now();
+            while start.elapsed() < CALIBRATION_TIME {}
+            let ticks = now() - start_ticks;
+            ticks as f64 / start.elapsed().as_secs_f64()
+        })
+        .collect::<Vec<_>>();
+    rounds.sort_by(f64::total_cmp);
+    rounds[rounds.len() / 2]
+}
+
+/// Nanoseconds since the clock was created
+pub struct InstantClock {
+    origin: Instant,
+}
+
+impl InstantClock {
+    pub fn new() -> Self {
+        InstantClock {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for InstantClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for InstantClock {
+    fn name(&self) -> &'static str {
+        "instant"
+    }
+
+    fn now(&self) -> u64 {
+        self.origin.elapsed().as_nanos() as u64
+    }
+
+    fn frequency(&self) -> f64 {
+        1e9
+    }
+}
+
+/// The virtual counter `cntvct_el0`, read after an `isb` so that it is not sampled early
+#[cfg(target_arch = "aarch64")]
+pub struct VirtualCounter {
+    frequency: f64,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl VirtualCounter {
+    /// Uses `cntfrq_el0` as the frequency, and only calibrates if firmware did not set it up
+    pub fn new() -> Self {
+        let cntfrq: u64;
+        unsafe { std::arch::asm!("mrs {}, cntfrq_el0", out(reg) cntfrq) };
+
+        let frequency = match cntfrq {
+            0 => calibrate(Self::read),
+            cntfrq => cntfrq as f64,
+        };
+
+        VirtualCounter { frequency }
+    }
+
+    #[inline(always)]
+    fn read() -> u64 {
+        let ticks: u64;
+        unsafe { std::arch::asm!("isb", "mrs {}, cntvct_el0", out(reg) ticks, options(nostack)) };
+        ticks
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Default for VirtualCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+impl Clock for VirtualCounter {
+    fn name(&self) -> &'static str {
+        "cntvct"
+    }
+
+    fn now(&self) -> u64 {
+        Self::read()
+    }
+
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
+}
+
+/// The time stamp counter, read after an `lfence` so that it is not sampled early
+#[cfg(target_arch = "x86_64")]
+pub struct Tsc {
+    frequency: f64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Tsc {
+    pub fn new() -> Self {
+        Tsc {
+            frequency: calibrate(Self::read),
+        }
+    }
+
+    #[inline(always)]
+    fn read() -> u64 {
+        unsafe {
+            std::arch::x86_64::_mm_lfence();
+            std::arch::x86_64::_rdtsc()
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for Tsc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Clock for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn now(&self) -> u64 {
+        Self::read()
+    }
+
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that never advances, with a fixed frequency
+    struct FixedClock(f64);
+
+    impl Clock for FixedClock {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn now(&self) -> u64 {
+            0
+        }
+
+        fn frequency(&self) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn converts_ticks_to_durations() {
+        // The frequency of a typical `cntvct_el0`
+        let clock = FixedClock(24e6);
+        assert_eq!(clock.to_duration(0), Duration::ZERO);
+        assert_eq!(clock.to_duration(24_000_000), Duration::from_secs(1));
+        assert_eq!(clock.to_duration(24), Duration::from_micros(1));
+
+        let clock = InstantClock::new();
+        assert_eq!(clock.to_duration(1_500), Duration::from_nanos(1_500));
+    }
+
+    #[test]
+    fn instant_clock_counts_nanoseconds() {
+        let clock = InstantClock::new();
+        let start = clock.now();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(clock.now() - start >= 10_000_000);
+    }
+
+    #[test]
+    fn auto_selects_the_cycle_counter() {
+        let clock = ClockKind::Auto.create().unwrap();
+        let expected = if cfg!(target_arch = "aarch64") {
+            "cntvct"
+        } else if cfg!(target_arch = "x86_64") {
+            "tsc"
+        } else {
+            "instant"
+        };
+        assert_eq!(clock.name(), expected);
+        assert!(clock.frequency() > 0.0);
+
+        assert_eq!(ClockKind::Instant.create().unwrap().name(), "instant");
+        let foreign = if cfg!(target_arch = "aarch64") {
+            ClockKind::Tsc
+        } else {
+            ClockKind::Cntvct
+        };
+        assert!(foreign.create().is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+/// A group of CPUs sharing the same micro-architecture and frequency domain
+#[derive(Clone, Debug)]
+pub struct Cluster {
+    /// CPU indices belonging to this cluster, in ascending order
+    pub cpus: Vec<usize>,
+    /// Value of `midr_el1`, if exposed by the kernel
+    pub midr: Option<u64>,
+    /// `cpuinfo_max_freq` in kHz, if cpufreq is available
+    pub max_freq_khz: Option<u64>,
+}
+
+impl Cluster {
+    /// The CPU used to run benchmarks for this cluster
+    pub fn representative(&self) -> usize {
+        self.cpus[0]
+    }
+
+    /// Human readable core name derived from the MIDR, e.g. `Cortex-X3`
+    pub fn core_type(&self) -> String {
+        match self.midr {
+            Some(midr) => core_name(midr)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("MIDR {:#010x}", midr)),
+            None => "unknown".to_string(),
+        }
+    }
+
+    /// Short identifier that is unique per cluster, e.g. `Cortex-X3-cpu8`
+    pub fn label(&self) -> String {
+        format!("{}-cpu{}", self.core_type(), self.representative())
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.core_type())?;
+        if let Some(khz) = self.max_freq_khz {
+            write!(f, " ({:.2} GHz)", khz as f64 / 1e6)?;
+        }
+        write!(f, " on cpu {}", self.representative())
+    }
+}
+
+/// Maps the implementer and part number fields of a MIDR to a core name
+fn core_name(midr: u64) -> Option<&'static str> {
+    let implementer = (midr >> 24) & 0xff;
+    let part = (midr >> 4) & 0xfff;
+
+    if implementer != 0x41 {
+        return None;
+    }
+
+    Some(match part {
+        0xd05 => "Cortex-A55",
+        0xd0b => "Cortex-A76",
+        0xd0d => "Cortex-A77",
+        0xd41 => "Cortex-A78",
+        0xd44 => "Cortex-X1",
+        0xd46 => "Cortex-A510",
+        0xd47 => "Cortex-A710",
+        0xd48 => "Cortex-X2",
+        0xd4d => "Cortex-A715",
+        0xd4e => "Cortex-X3",
+        0xd80 => "Cortex-A520",
+        0xd81 => "Cortex-A720",
+        0xd82 => "Cortex-X4",
+        0xd85 => "Cortex-X925",
+        0xd87 => "Cortex-A725",
+        _ => return None,
+    })
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Returns the indices of all CPUs listed below `root`
+fn cpu_indices(root: &Path) -> Vec<usize> {
+    let mut cpus = fs::read_dir(root)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name();
+                    name.to_str()?.strip_prefix("cpu")?.parse::<usize>().ok()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    cpus.sort_unstable();
+    cpus
+}
+
+/// Parses a kernel CPU list such as `0-3,8`
+pub fn parse_cpu_list(list: &str) -> Vec<usize> {
+    list.trim()
+        .split(',')
+        .filter(|range| !range.is_empty())
+        .filter_map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            Some(start.parse::<usize>().ok()?..=end.parse::<usize>().ok()?)
+        })
+        .flatten()
+        .collect()
+}
+
+/// Discovers the CPU clusters of this machine, fastest cluster first.
+///
+/// If sysfs cannot be read, e.g. in some containers, a single cluster with the CPU the calling
+/// thread runs on is returned instead.
+pub fn discover_clusters() -> Vec<Cluster> {
+    let clusters = discover_clusters_in(Path::new(SYSFS_CPU));
+    if !clusters.is_empty() {
+        return clusters;
+    }
+
+    let cpu = unsafe { libc::sched_getcpu() }.max(0) as usize;
+    eprintln!(
+        "could not discover CPUs from {}, only measuring cpu {}",
+        SYSFS_CPU, cpu
+    );
+    vec![Cluster {
+        cpus: vec![cpu],
+        midr: None,
+        max_freq_khz: None,
+    }]
+}
+
+/// Discovers CPU clusters from a sysfs tree rooted at `root` (normally `/sys/devices/system/cpu`).
+///
+/// CPUs are grouped by MIDR and maximum frequency, so cores of the same type that are clocked
+/// differently end up in separate clusters. Offline CPUs are left out.
+pub fn discover_clusters_in(root: &Path) -> Vec<Cluster> {
+    let mut clusters: Vec<Cluster> = Vec::new();
+
+    for cpu in cpu_indices(root) {
+        let dir = root.join(format!("cpu{}", cpu));
+        // CPUs that cannot be taken offline, usually cpu0, have no `online` file
+        if read_trimmed(&dir.join("online")).is_some_and(|online| online == "0") {
+            continue;
+        }
+        let midr = read_trimmed(&dir.join("regs/identification/midr_el1"))
+            .and_then(|s| u64::from_str_radix(s.trim_start_matches("0x"), 16).ok());
+        let max_freq_khz =
+            read_trimmed(&dir.join("cpufreq/cpuinfo_max_freq")).and_then(|s| s.parse().ok());
+
+        match clusters
+            .iter_mut()
+            .find(|c| c.midr == midr && c.max_freq_khz == max_freq_khz)
+        {
+            Some(cluster) => cluster.cpus.push(cpu),
+            None => clusters.push(Cluster {
+                cpus: vec![cpu],
+                midr,
+                max_freq_khz,
+            }),
+        }
+    }
+
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.max_freq_khz));
+    clusters
+}
+
+/// Pins the calling thread to `cpu`
+pub fn pin_to_cpu(cpu: usize) -> io::Result<()> {
+    pin_to_cpus(&[cpu])
+}
+
+/// Restricts the calling thread to the given set of CPUs
+pub fn pin_to_cpus(cpus: &[usize]) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds `cpu<cpu>` to a fake sysfs tree, with the given MIDR, maximum frequency and `online`
+    fn add_cpu(
+        root: &Path,
+        cpu: usize,
+        midr: Option<&str>,
+        khz: Option<u64>,
+        online: Option<&str>,
+    ) {
+        let dir = root.join(format!("cpu{}", cpu));
+        fs::create_dir_all(&dir).unwrap();
+        if let Some(midr) = midr {
+            fs::create_dir_all(dir.join("regs/identification")).unwrap();
+            fs::write(
+                dir.join("regs/identification/midr_el1"),
+                format!("{}\n", midr),
+            )
+            .unwrap();
+        }
+        if let Some(khz) = khz {
+            fs::create_dir_all(dir.join("cpufreq")).unwrap();
+            fs::write(dir.join("cpufreq/cpuinfo_max_freq"), format!("{}\n", khz)).unwrap();
+        }
+        if let Some(online) = online {
+            fs::write(dir.join("online"), format!("{}\n", online)).unwrap();
+        }
+    }
+
+    #[test]
+    fn groups_cpus_by_core_type_and_frequency() {
+        const A510: &str = "0x00000000410fd461";
+        const A715: &str = "0x00000000410fd4d1";
+        const X3: &str = "0x00000000410fd4e1";
+
+        let root = tempfile::tempdir().unwrap();
+        for cpu in 0..4 {
+            add_cpu(root.path(), cpu, Some(A510), Some(1_704_000), None);
+        }
+        add_cpu(root.path(), 4, Some(A715), Some(2_367_000), Some("1"));
+        add_cpu(root.path(), 5, Some(A715), Some(2_367_000), Some("1"));
+        // Same core type, clocked higher
+        add_cpu(root.path(), 6, Some(A715), Some(2_850_000), Some("1"));
+        add_cpu(root.path(), 7, Some(X3), Some(2_910_000), Some("1"));
+        // Entries that are not CPUs
+        fs::create_dir(root.path().join("cpufreq")).unwrap();
+        fs::create_dir(root.path().join("cpuidle")).unwrap();
+
+        let clusters = discover_clusters_in(root.path());
+        let cpus = clusters.iter().map(|c| c.cpus.clone()).collect::<Vec<_>>();
+        assert_eq!(cpus, [vec![7], vec![6], vec![4, 5], vec![0, 1, 2, 3]]);
+        assert_eq!(clusters[0].label(), "Cortex-X3-cpu7");
+        assert_eq!(clusters[3].to_string(), "Cortex-A510 (1.70 GHz) on cpu 0");
+    }
+
+    #[test]
+    fn skips_offline_cpus() {
+        let root = tempfile::tempdir().unwrap();
+        add_cpu(root.path(), 0, None, None, None);
+        add_cpu(root.path(), 1, None, None, Some("0"));
+        add_cpu(root.path(), 2, None, None, Some("1"));
+
+        let clusters = discover_clusters_in(root.path());
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].cpus, [0, 2]);
+        assert_eq!(clusters[0].core_type(), "unknown");
+    }
+
+    #[test]
+    fn unreadable_sysfs_has_no_clusters() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(discover_clusters_in(&root.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,8\n"), [0, 1, 2, 3, 8]);
+        assert_eq!(parse_cpu_list("5"), [5]);
+        assert!(parse_cpu_list("").is_empty());
+    }
+}
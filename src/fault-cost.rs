@@ -0,0 +1,115 @@
+use clap::Parser;
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::fault::{faults, install_handler, last_mismatch, try_store};
+use mte_measurement::region::Region;
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use mte_measurement::{set_mte_mode, set_tag, stg, MTEMode};
+use std::time::{Duration, Instant};
+
+const TAG_MASK: u64 = 0x0f00_0000_0000_0000;
+
+#[derive(Parser)]
+struct Args {
+    /// Faults per timed sample
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u32).range(1..))]
+    faults: u32,
+    /// Timed samples per mode
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    iters: u32,
+    #[command(flatten)]
+    cooldown: CooldownConfig,
+}
+
+/// Time per [`try_store`] to `p`, each followed by a system call in async mode so a fault is
+/// reported
+fn stores(p: *mut u8, mode: MTEMode, n: u32) -> Duration {
+    let start = Instant::now();
+    for _ in 0..n {
+        unsafe {
+            try_store(p);
+            if let MTEMode::Async = mode {
+                libc::syscall(libc::SYS_getppid);
+            }
+        }
+    }
+    start.elapsed() / n
+}
+
+/// Checks what the handler decoded from the last synchronous fault on `wrong`
+fn check_mismatch(wrong: *mut u8, tag: u64) {
+    let (addr, memory_tag) = last_mismatch();
+
+    assert_eq!(
+        addr & !TAG_MASK,
+        wrong as u64 & !TAG_MASK,
+        "fault reported at the wrong address"
+    );
+    assert_eq!(memory_tag, tag, "read back the wrong memory tag");
+    // Kernels before 5.11 clear the tag bits of the reported address
+    if addr & TAG_MASK != 0 {
+        assert_eq!(addr & TAG_MASK, wrong as u64 & TAG_MASK);
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    install_handler().expect("could not install signal handler");
+
+    let mut region = Region::map(4096, 0).expect("could not map memory");
+    let tag = 5 << 56;
+    unsafe { stg(region.as_mut_slice(), tag) };
+    let right = set_tag(region.as_mut_slice().as_mut_ptr(), tag);
+    let wrong = set_tag(right, tag ^ (1 << 56));
+
+    let cooldown = Cooldown::new(args.cooldown);
+
+    for cluster in discover_clusters() {
+        pin_to_cpu(cluster.representative()).expect("could not pin to cpu");
+
+        let mut table = format!(
+            "{:8} {:>12} {:>12} {:>12}",
+            "mode", "no fault", "fault", "per fault"
+        );
+
+        for (name, mode) in [("sync", MTEMode::Sync), ("async", MTEMode::Async)] {
+            unsafe {
+                set_mte_mode(mode);
+            }
+            cooldown.wait();
+
+            let mut baseline = Duration::ZERO;
+            let mut faulting = Duration::ZERO;
+            for _ in 0..args.iters {
+                baseline += stores(right, mode, args.faults);
+
+                let before = faults();
+                faulting += stores(wrong, mode, args.faults);
+                assert_eq!(
+                    faults() - before,
+                    args.faults as u64,
+                    "not every store raised a fault"
+                );
+                if let MTEMode::Sync = mode {
+                    check_mismatch(wrong, tag);
+                }
+            }
+            let (baseline, faulting) = (baseline / args.iters, faulting / args.iters);
+            let cost = faulting.saturating_sub(baseline);
+
+            println!(
+                "{} {}: {:.2?} per fault ({:.2?} per store with fault, {:.2?} without)",
+                cluster, name, cost, faulting, baseline
+            );
+            table += &format!(
+                "\n{:8} {:>12} {:>12} {:>12}",
+                name,
+                format!("{:.2?}", baseline),
+                format!("{:.2?}", faulting),
+                format!("{:.2?}", cost)
+            );
+        }
+
+        println!("{} tag check fault cost:\n{}", cluster, table);
+    }
+}
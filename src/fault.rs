@@ -0,0 +1,169 @@
+use std::cell::UnsafeCell;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::load_tag;
+
+/// `si_code` of a `SIGSEGV` caused by an asynchronous tag check fault
+pub const SEGV_MTEAERR: i32 = 8;
+/// `si_code` of a `SIGSEGV` caused by a synchronous tag check fault
+pub const SEGV_MTESERR: i32 = 9;
+
+/// Keeps the tag bits of `si_addr` instead of clearing them (Linux 5.11)
+const SA_EXPOSE_TAGBITS: i32 = 0x800;
+
+static FAULTS: AtomicU64 = AtomicU64::new(0);
+static LAST_FAULT: AtomicU64 = AtomicU64::new(0);
+static LAST_ADDR: AtomicU64 = AtomicU64::new(0);
+static LAST_TAG: AtomicU64 = AtomicU64::new(0);
+
+/// Set while [`try_store`] can be resumed from a synchronous fault
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// A `sigjmp_buf`, larger than the one of glibc (312 bytes) and bionic (256 bytes) on aarch64
+#[repr(C, align(16))]
+struct JmpBuf(UnsafeCell<[u64; 64]>);
+
+unsafe impl Sync for JmpBuf {}
+
+static JMP_BUF: JmpBuf = JmpBuf(UnsafeCell::new([0; 64]));
+
+extern "C" {
+    fn siglongjmp(env: *mut u64, val: i32) -> !;
+}
+
+// `sigsetjmp` returns twice, which Rust code cannot soundly call. The call and the store that may
+// fault are therefore written in assembly, which returns 1 if it was resumed by `siglongjmp`.
+#[cfg(target_arch = "aarch64")]
+std::arch::global_asm!(
+    ".pushsection .text.mte_measurement_try_store, \"ax\", %progbits",
+    ".balign 4",
+    ".globl mte_measurement_try_store",
+    ".type mte_measurement_try_store, %function",
+    "mte_measurement_try_store:",
+    "stp x29, x30, [sp, #-32]!",
+    "mov x29, sp",
+    "str x19, [sp, #16]",
+    "mov x19, x0",
+    "mov x0, x1",
+    "mov w1, #1",
+    "bl {sigsetjmp}",
+    "cbnz w0, 1f",
+    "strb wzr, [x19]",
+    "1:",
+    "ldr x19, [sp, #16]",
+    "ldp x29, x30, [sp], #32",
+    "ret",
+    ".size mte_measurement_try_store, . - mte_measurement_try_store",
+    ".popsection",
+    sigsetjmp = sym sigsetjmp,
+);
+
+#[cfg(target_arch = "aarch64")]
+extern "C" {
+    // glibc only exports `sigsetjmp` as the `__sigsetjmp` symbol behind a macro
+    #[cfg_attr(target_env = "gnu", link_name = "__sigsetjmp")]
+    fn sigsetjmp(env: *mut u64, savemask: i32) -> i32;
+    fn mte_measurement_try_store(p: *mut u8, env: *mut u64) -> i32;
+}
+
+/// Nanoseconds on the monotonic clock, safe to call from a signal handler
+pub fn now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+type Handler = extern "C" fn(i32, *mut libc::siginfo_t, *mut libc::c_void);
+
+fn record() {
+    LAST_FAULT.store(now_ns(), Ordering::SeqCst);
+    FAULTS.fetch_add(1, Ordering::SeqCst);
+}
+
+extern "C" fn handle(_signal: i32, info: *mut libc::siginfo_t, _context: *mut libc::c_void) {
+    let code = unsafe { (*info).si_code };
+
+    match code {
+        // The kernel clears the pending asynchronous fault before delivering the signal, so
+        // returning simply continues where the thread was interrupted
+        SEGV_MTEAERR => record(),
+        // Only the store of `try_store` is expected to fault, anything else is a real bug
+        SEGV_MTESERR if ARMED.swap(false, Ordering::SeqCst) => unsafe {
+            let addr = (*info).si_addr() as u64;
+            LAST_ADDR.store(addr, Ordering::SeqCst);
+            LAST_TAG.store(load_tag(addr as *const u8), Ordering::SeqCst);
+            record();
+            // Nothing on this frame needs to be dropped
+            siglongjmp(JMP_BUF.0.get() as *mut u64, 1)
+        },
+        // Fall back to the default action, which terminates the process once the faulting
+        // instruction runs again
+        _ => unsafe {
+            libc::signal(libc::SIGSEGV, libc::SIG_DFL);
+        },
+    }
+}
+
+/// Installs a `SIGSEGV` handler that counts tag check faults and records when they arrive.
+///
+/// Asynchronous faults are resumed where the thread was interrupted. Synchronous faults are only
+/// recovered from within [`try_store`], any other segmentation fault still terminates the process.
+pub fn install_handler() -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handle as Handler as usize;
+        action.sa_flags = libc::SA_SIGINFO | SA_EXPOSE_TAGBITS;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGSEGV, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Number of tag check faults handled so far
+pub fn faults() -> u64 {
+    FAULTS.load(Ordering::SeqCst)
+}
+
+/// Time of the last handled fault in [`now_ns`] nanoseconds
+pub fn last_fault() -> u64 {
+    LAST_FAULT.load(Ordering::SeqCst)
+}
+
+/// Address of the last synchronous fault as reported in `si_addr`, and the tag of the memory
+/// there in bits 56-59
+pub fn last_mismatch() -> (u64, u64) {
+    (
+        LAST_ADDR.load(Ordering::SeqCst),
+        LAST_TAG.load(Ordering::SeqCst),
+    )
+}
+
+/// Stores a zero byte to `p`, returning whether the store raised a synchronous tag check fault.
+///
+/// The fault is recovered from with `siglongjmp` back into this function, which restores the
+/// signal mask saved by `sigsetjmp` beforehand, so the store that faulted does not happen. Only
+/// one thread may call this at a time.
+///
+/// # Safety
+///
+/// `p` has to point to writable memory and the handler has to be installed with
+/// [`install_handler`].
+pub unsafe fn try_store(p: *mut u8) -> bool {
+    ARMED.store(true, Ordering::SeqCst);
+    #[cfg(target_arch = "aarch64")]
+    let faulted = mte_measurement_try_store(p, JMP_BUF.0.get() as *mut u64) != 0;
+    #[cfg(not(target_arch = "aarch64"))]
+    let faulted = {
+        p.write_volatile(0);
+        false
+    };
+    ARMED.store(false, Ordering::SeqCst);
+    faulted
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+
+use crate::cpu::pin_to_cpus;
+
+const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+/// How the CPU frequency is tracked during a measurement
+#[derive(Args, Clone, Debug)]
+pub struct FreqConfig {
+    /// Interval of the background frequency sampler in milliseconds
+    #[arg(long = "freq-interval", default_value_t = 100)]
+    pub interval_ms: u64,
+    /// Flag a measurement as throttled if the frequency drops below this fraction of the maximum
+    #[arg(long = "min-freq-fraction", default_value_t = 0.9)]
+    pub min_fraction: f64,
+}
+
+impl Default for FreqConfig {
+    fn default() -> Self {
+        FreqConfig {
+            interval_ms: 100,
+            min_fraction: 0.9,
+        }
+    }
+}
+
+/// One reading of the cpufreq state of a CPU
+#[derive(Clone, Debug)]
+pub struct FreqSample {
+    /// Time since the start of the trace
+    pub at: Duration,
+    /// `scaling_cur_freq` in kHz
+    pub cur_khz: Option<u64>,
+    /// `scaling_governor`
+    pub governor: Option<String>,
+}
+
+/// The cpufreq directory of a single CPU
+#[derive(Clone, Debug)]
+pub struct CpuFreq {
+    dir: PathBuf,
+}
+
+impl CpuFreq {
+    pub fn new(cpu: usize) -> Self {
+        Self::new_in(Path::new(SYSFS_CPU), cpu)
+    }
+
+    /// Uses the sysfs tree rooted at `root` (normally `/sys/devices/system/cpu`)
+    pub fn new_in(root: &Path, cpu: usize) -> Self {
+        CpuFreq {
+            dir: root.join(format!("cpu{}/cpufreq", cpu)),
+        }
+    }
+
+    fn read(&self, file: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(file))
+            .ok()
+            .map(|s| s.trim().to_string())
+    }
+
+    /// `cpuinfo_max_freq` in kHz
+    pub fn max_khz(&self) -> Option<u64> {
+        self.read("cpuinfo_max_freq")?.parse().ok()
+    }
+
+    pub fn sample(&self, at: Duration) -> FreqSample {
+        FreqSample {
+            at,
+            cur_khz: self.read("scaling_cur_freq").and_then(|s| s.parse().ok()),
+            governor: self.read("scaling_governor"),
+        }
+    }
+}
+
+/// Frequency readings before, during and after a measurement
+#[derive(Clone, Debug)]
+pub struct FreqTrace {
+    pub max_khz: Option<u64>,
+    /// Taken right after the cooldown, when an idle core usually runs at its lowest frequency
+    pub before: FreqSample,
+    pub during: Vec<FreqSample>,
+    pub after: FreqSample,
+}
+
+impl FreqTrace {
+    pub fn samples(&self) -> impl Iterator<Item = &FreqSample> {
+        std::iter::once(&self.before)
+            .chain(self.during.iter())
+            .chain(std::iter::once(&self.after))
+    }
+
+    /// Lowest frequency observed while the measurement ran in kHz
+    pub fn min_khz(&self) -> Option<u64> {
+        self.during.iter().filter_map(|s| s.cur_khz).min()
+    }
+
+    /// Highest frequency observed while the measurement ran in kHz
+    pub fn max_observed_khz(&self) -> Option<u64> {
+        self.during.iter().filter_map(|s| s.cur_khz).max()
+    }
+
+    /// Whether the frequency dropped below `min_fraction` of the maximum frequency while the
+    /// measurement ran
+    pub fn throttled(&self, min_fraction: f64) -> bool {
+        match (self.min_khz(), self.max_khz) {
+            (Some(min), Some(max)) => (min as f64) < max as f64 * min_fraction,
+            _ => false,
+        }
+    }
+
+    /// Whether the governor changed during the measurement
+    pub fn governor_changed(&self) -> bool {
+        let mut governors = self.samples().filter_map(|s| s.governor.as_ref());
+        match governors.next() {
+            Some(first) => governors.any(|g| g != first),
+            None => false,
+        }
+    }
+}
+
+/// Samples the frequency of a CPU on a background thread.
+///
+/// The sampler thread is kept off the measured CPU so that it does not disturb the measurement.
+pub struct Sampler {
+    freq: CpuFreq,
+    start: Instant,
+    before: FreqSample,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Vec<FreqSample>>,
+}
+
+impl Sampler {
+    pub fn start(cpu: usize, interval: Duration) -> Self {
+        let freq = CpuFreq::new(cpu);
+        let start = Instant::now();
+        let before = freq.sample(Duration::ZERO);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let freq = freq.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let ncpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as usize;
+                let others = (0..ncpus).filter(|&c| c != cpu).collect::<Vec<_>>();
+                if !others.is_empty() {
+                    let _ = pin_to_cpus(&others);
+                }
+
+                let mut samples = Vec::new();
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(interval);
+                    samples.push(freq.sample(start.elapsed()));
+                }
+                samples
+            })
+        };
+
+        Sampler {
+            freq,
+            start,
+            before,
+            stop,
+            handle,
+        }
+    }
+
+    pub fn stop(self) -> FreqTrace {
+        self.stop.store(true, Ordering::Relaxed);
+        let during = self.handle.join().expect("frequency sampler panicked");
+
+        FreqTrace {
+            max_khz: self.freq.max_khz(),
+            before: self.before,
+            during,
+            after: self.freq.sample(self.start.elapsed()),
+        }
+    }
+}
@@ -0,0 +1,705 @@
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use rand::random;
+
+use crate::clock::{Clock, ClockKind};
+use crate::cpu::{pin_to_cpu, Cluster};
+use crate::freq::{FreqConfig, FreqTrace, Sampler};
+use crate::perf::{CounterGroup, CounterValue, Counters, PerfConfig};
+use crate::plan::Slot;
+use crate::plot::unique;
+use crate::region::{page_faults, AllocPolicy, PagePolicy, Region};
+use crate::results::{format_timestamp, Environment, Record, ResultStore, ResultsConfig};
+use crate::stats;
+use crate::stgp_fill;
+use crate::thermal::{Cooldown, CooldownConfig};
+use crate::variant::Variant;
+
+/// Written to memory that is reused before validated variants run, so leftover data shows up
+pub const FILL_PATTERN: u64 = 0xa5a5_a5a5_a5a5_a5a5;
+
+/// Options shared by all binaries that use the [`Harness`]
+#[derive(Args, Clone, Debug, Default)]
+pub struct HarnessConfig {
+    /// Time source used to time each iteration
+    #[arg(long, value_enum, default_value_t = ClockKind::Auto)]
+    pub clock: ClockKind,
+    /// How memory is provided to each iteration
+    #[arg(long, value_enum, default_value_t = AllocPolicy::Fresh)]
+    pub alloc: AllocPolicy,
+    /// Page sizes to measure, comma separated; unsupported ones are skipped
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [PagePolicy::Default])]
+    pub pages: Vec<PagePolicy>,
+    #[command(flatten)]
+    pub cooldown: CooldownConfig,
+    #[command(flatten)]
+    pub freq: FreqConfig,
+    #[command(flatten)]
+    pub perf: PerfConfig,
+    #[command(flatten)]
+    pub results: ResultsConfig,
+    /// Do not check the tags and data of the memory after every iteration of a variant
+    #[arg(long)]
+    pub no_validate: bool,
+    #[command(flatten)]
+    pub iterations: IterationConfig,
+}
+
+/// How many iterations are run before and during a measurement
+#[derive(Args, Clone, Debug)]
+pub struct IterationConfig {
+    /// Run untimed iterations for at least this many milliseconds before measuring
+    #[arg(long, default_value_t = 100)]
+    pub warmup_ms: u64,
+    /// Choose the number of timed iterations so that they take about this many milliseconds in
+    /// total over all rounds, estimated from the warmup, instead of the binary's fixed count
+    #[arg(long)]
+    pub target_ms: Option<u64>,
+    /// Stop once the coefficient of variation of the last `--cv-window` iterations drops below
+    /// this many percent
+    #[arg(long)]
+    pub cv_threshold: Option<f64>,
+    /// Number of recent iterations the steady-state check looks at
+    #[arg(long, default_value_t = 10)]
+    pub cv_window: usize,
+}
+
+impl Default for IterationConfig {
+    fn default() -> Self {
+        IterationConfig {
+            warmup_ms: 100,
+            target_ms: None,
+            cv_threshold: None,
+            cv_window: 10,
+        }
+    }
+}
+
+/// Bounds of the iteration count chosen for `--target-ms`
+const MIN_ITERS: u64 = 10;
+const MAX_ITERS: u64 = 1_000_000;
+
+/// Result of measuring one variant
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    pub name: String,
+    /// Size of the memory passed to each iteration in bytes
+    pub size: usize,
+    /// How memory was provided to each iteration
+    pub alloc: AllocPolicy,
+    /// Which page size backed the memory
+    pub pages: PagePolicy,
+    /// CPU the measurement was pinned to
+    pub cpu: Option<usize>,
+    /// Label of the cluster of `cpu`, e.g. `Cortex-X3-cpu8`
+    pub core: Option<String>,
+    /// Duration of every timed iteration in clock ticks
+    pub samples: Vec<u64>,
+    /// Name of the clock used for timing
+    pub clock: &'static str,
+    /// Frequency of the clock in ticks per second
+    pub tick_frequency: f64,
+    /// Temperature in °C when the measurement started
+    pub temp_start: Option<f64>,
+    /// Temperature in °C when the measurement finished
+    pub temp_end: Option<f64>,
+    /// Frequency of the pinned CPU over the course of the measurement
+    pub freq: Option<FreqTrace>,
+    /// Whether the frequency dropped below the configured fraction of the maximum
+    pub throttled: bool,
+    /// Performance counters accumulated over all timed iterations
+    pub counters: Vec<CounterValue>,
+    /// Page faults taken during the timed sections, from `getrusage`
+    pub faults: u64,
+    /// Round and position of the measurement if the variants were interleaved
+    pub slot: Option<Slot>,
+}
+
+impl Measurement {
+    fn to_duration(&self, ticks: f64) -> Duration {
+        Duration::from_secs_f64(ticks / self.tick_frequency)
+    }
+
+    pub fn total_ticks(&self) -> u64 {
+        self.samples.iter().sum()
+    }
+
+    pub fn mean_ticks(&self) -> f64 {
+        self.total_ticks() as f64 / self.samples.len().max(1) as f64
+    }
+
+    pub fn total(&self) -> Duration {
+        self.to_duration(self.total_ticks() as f64)
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.to_duration(self.mean_ticks())
+    }
+
+    /// Bytes processed per second
+    pub fn throughput(&self) -> f64 {
+        self.size as f64 / self.mean().as_secs_f64()
+    }
+
+    pub fn faults_per_iter(&self) -> f64 {
+        self.faults as f64 / self.samples.len().max(1) as f64
+    }
+
+    /// Duration of every timed iteration
+    pub fn durations(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.samples.iter().map(|&ticks| self.to_duration(ticks as f64))
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.2?} ({:.0} {} ticks, {:.2} GB/s)",
+            self.name,
+            self.mean(),
+            self.mean_ticks(),
+            self.clock,
+            self.throughput() / 1e9
+        )?;
+        write!(
+            f,
+            " [{} iters, {}, {} pages, {:.0} faults]",
+            self.samples.len(),
+            self.alloc.name(),
+            self.pages.name(),
+            self.faults_per_iter()
+        )?;
+        if let (Some(start), Some(end)) = (self.temp_start, self.temp_end) {
+            write!(f, " ({:.1} °C -> {:.1} °C)", start, end)?;
+        }
+        if let Some(freq) = &self.freq {
+            if let (Some(min), Some(max)) = (freq.min_khz(), freq.max_observed_khz()) {
+                write!(f, " [{:.2}-{:.2} GHz]", min as f64 / 1e6, max as f64 / 1e6)?;
+            }
+            if freq.governor_changed() {
+                write!(f, " [governor changed]")?;
+            }
+        }
+        if self.throttled {
+            write!(f, " [throttled]")?;
+        }
+        if !self.counters.is_empty() {
+            let iters = self.samples.len().max(1) as f64;
+            let counters = self
+                .counters
+                .iter()
+                .map(|c| format!("{}={:.0}", c.counter, c.value as f64 / iters))
+                .collect::<Vec<_>>();
+            write!(f, "\n    per iteration: {}", counters.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats the throughput of `measurements` as a table with one row per variant and one column
+/// per page policy
+pub fn throughput_table(measurements: &[Measurement]) -> String {
+    let names = unique(measurements.iter().map(|m| m.name.as_str()));
+    let pages = unique(measurements.iter().map(|m| m.pages));
+
+    let width = names
+        .iter()
+        .map(|n| n.len())
+        .max()
+        .unwrap_or(0)
+        .max("variant".len());
+    let mut table = format!("{:width$}", "variant");
+    for p in &pages {
+        table += &format!(" {:>12}", p.name());
+    }
+
+    for name in names {
+        table += &format!("\n{:width$}", name);
+        for &p in &pages {
+            // Interleaved variants have one measurement per round
+            let throughputs = measurements
+                .iter()
+                .filter(|m| m.name == name && m.pages == p)
+                .map(|m| m.throughput())
+                .collect::<Vec<_>>();
+            let cell = match throughputs.len() {
+                0 => "-".to_string(),
+                n => format!(
+                    "{:.2} GB/s",
+                    throughputs.iter().sum::<f64>() / n as f64 / 1e9
+                ),
+            };
+            table += &format!(" {:>12}", cell);
+        }
+    }
+
+    table
+}
+
+/// Runs variants on tagged memory and cools down between them
+pub struct Harness {
+    clock: Box<dyn Clock>,
+    alloc: AllocPolicy,
+    page_policies: Vec<PagePolicy>,
+    pages: PagePolicy,
+    cooldown: Cooldown,
+    freq: FreqConfig,
+    counters: Vec<CounterGroup>,
+    cpu: Option<usize>,
+    core: Option<String>,
+    store: Option<Store>,
+    validate: bool,
+    slot: Option<Slot>,
+    /// Rounds of the plan that share the `--target-ms` budget of each variant
+    rounds: usize,
+    iterations: IterationConfig,
+}
+
+/// Where measurements are saved, if a results directory was given
+struct Store {
+    store: ResultStore,
+    run: String,
+    binary: String,
+    environment: Environment,
+}
+
+impl Harness {
+    pub fn new(config: HarnessConfig) -> Self {
+        let store = config.results.dir.map(|dir| {
+            let environment = Environment::capture();
+            let run = config
+                .results
+                .run
+                .unwrap_or_else(|| format_timestamp(environment.timestamp).replace(':', ""));
+            let binary = std::env::args()
+                .next()
+                .as_deref()
+                .and_then(|arg0| std::path::Path::new(arg0).file_stem())
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            eprintln!("saving results as run {} to {}", run, dir.display());
+
+            Store {
+                store: ResultStore::new(dir),
+                run,
+                binary,
+                environment,
+            }
+        });
+
+        Harness {
+            clock: config.clock.create().expect("could not create clock"),
+            alloc: config.alloc,
+            page_policies: config.pages,
+            pages: PagePolicy::Default,
+            cooldown: Cooldown::new(config.cooldown),
+            freq: config.freq,
+            counters: config.perf.groups(),
+            cpu: None,
+            core: None,
+            store,
+            validate: !config.no_validate,
+            iterations: config.iterations,
+            slot: None,
+            rounds: 1,
+        }
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Whether variants are checked after they ran, i.e. `--no-validate` was not given
+    pub fn validates(&self) -> bool {
+        self.validate
+    }
+
+    /// The configured page policies that are supported by the kernel, reporting skipped ones
+    pub fn page_policies(&self) -> Vec<PagePolicy> {
+        if self.page_policies.is_empty() {
+            return vec![PagePolicy::Default];
+        }
+
+        self.page_policies
+            .iter()
+            .copied()
+            .filter(|pages| match pages.check() {
+                Ok(()) => true,
+                Err(e) => {
+                    eprintln!("skipping {} pages: {}", pages.name(), e);
+                    false
+                }
+            })
+            .collect()
+    }
+
+    /// Records subsequent measurements as taken at `slot` of an interleaved plan
+    pub fn set_slot(&mut self, slot: Option<Slot>) {
+        self.slot = slot;
+    }
+
+    /// Splits the `--target-ms` budget of every variant evenly over `rounds` measurements, one
+    /// per round of an interleaved plan
+    pub fn set_rounds(&mut self, rounds: usize) {
+        self.rounds = rounds.max(1);
+    }
+
+    /// Backs the memory of subsequent measurements with `pages`
+    pub fn set_page_policy(&mut self, pages: PagePolicy) {
+        self.pages = pages;
+    }
+
+    /// Pins the calling thread to the representative CPU of `cluster` and tracks its frequency in
+    /// subsequent measurements
+    pub fn pin(&mut self, cluster: &Cluster) -> io::Result<()> {
+        let cpu = cluster.representative();
+        pin_to_cpu(cpu)?;
+        self.cpu = Some(cpu);
+        self.core = Some(cluster.label());
+        Ok(())
+    }
+
+    /// Waits for the device to cool down, then times `iters` calls of `f` on a `size` byte mapping
+    /// with `PROT_MTE`, provided according to the configured [`AllocPolicy`] and [`PagePolicy`].
+    pub fn measure(
+        &mut self,
+        name: &str,
+        size: usize,
+        iters: u64,
+        mut f: impl FnMut(&mut [u8]),
+    ) -> Measurement {
+        self.measure_prepared(name, size, iters, |_| (), |mem, ()| f(mem))
+    }
+
+    /// Like [`Harness::measure`], but calls `setup` on the memory before every iteration, outside
+    /// the timed section, and passes its result to `f`
+    pub fn measure_prepared<T: Copy>(
+        &mut self,
+        name: &str,
+        size: usize,
+        iters: u64,
+        setup: impl FnMut(&mut [u8]) -> T,
+        f: impl FnMut(&mut [u8], T),
+    ) -> Measurement {
+        self.measure_checked(name, size, iters, setup, f, |_, _| {})
+    }
+
+    /// Like [`Harness::measure`] for a tagging `variant`, passing a random tag to `f`.
+    ///
+    /// Unless validation is disabled, every iteration is checked afterwards, outside the timed
+    /// section, and a variant that leaves the wrong tags or data behind panics. Memory that is
+    /// already faulted in is first filled with a pattern, so the zero check cannot pass by
+    /// accident; fresh mappings are left as the kernel zeroed them.
+    pub fn measure_variant(
+        &mut self,
+        variant: &Variant,
+        size: usize,
+        iters: u64,
+        f: impl FnMut(&mut [u8], u64),
+    ) -> Measurement {
+        let validate = self.validate;
+        let fill = validate && self.alloc != AllocPolicy::Fresh;
+
+        self.measure_checked(
+            variant.name,
+            size,
+            iters,
+            |mem| {
+                if fill {
+                    unsafe { stgp_fill(mem, 0, FILL_PATTERN) };
+                }
+                random()
+            },
+            f,
+            |mem, tag| {
+                if validate {
+                    if let Err(e) = unsafe { variant.validate(mem, tag) } {
+                        panic!("validation failed: {}", e);
+                    }
+                }
+            },
+        )
+    }
+
+    /// Like [`Harness::measure`], but unless validation is disabled, checks every iteration with
+    /// `validate` afterwards, outside the timed section, and panics if it fails. As in
+    /// [`Harness::measure_variant`], memory that is already faulted in is first retagged to 0 and
+    /// filled with a pattern.
+    pub fn measure_validated(
+        &mut self,
+        name: &str,
+        size: usize,
+        iters: u64,
+        mut f: impl FnMut(&mut [u8]),
+        validate: impl Fn(&[u8]) -> Result<(), String>,
+    ) -> Measurement {
+        let check = self.validate;
+        let fill = check && self.alloc != AllocPolicy::Fresh;
+
+        self.measure_checked(
+            name,
+            size,
+            iters,
+            |mem| {
+                if fill {
+                    unsafe { stgp_fill(mem, 0, FILL_PATTERN) };
+                }
+            },
+            |mem, ()| f(mem),
+            |mem, ()| {
+                if check {
+                    if let Err(e) = validate(mem) {
+                        panic!("validation failed: {}", e);
+                    }
+                }
+            },
+        )
+    }
+
+    /// Like [`Harness::measure_prepared`], but calls `check` with the memory and the result of
+    /// `setup` after every iteration, outside the timed section
+    fn measure_checked<T: Copy>(
+        &mut self,
+        name: &str,
+        size: usize,
+        iters: u64,
+        mut setup: impl FnMut(&mut [u8]) -> T,
+        mut f: impl FnMut(&mut [u8], T),
+        mut check: impl FnMut(&mut [u8], T),
+    ) -> Measurement {
+        self.cooldown.wait();
+
+        let counters = Counters::open(&self.counters, self.cpu);
+        let temp_start = self.cooldown.temperature();
+        let sampler = self
+            .cpu
+            .map(|cpu| Sampler::start(cpu, Duration::from_millis(self.freq.interval_ms)));
+        let mut faults = 0;
+        let mut reused = (self.alloc == AllocPolicy::Reused)
+            .then(|| Region::prepare(size, self.alloc, self.pages).expect("could not map memory"));
+        let clock = self.clock.as_ref();
+
+        let mut iteration = |timed: bool| {
+            let mut fresh;
+            let region = match reused.as_mut() {
+                Some(region) => region,
+                None => {
+                    fresh = Region::prepare(size, self.alloc, self.pages)
+                        .expect("could not map memory");
+                    &mut fresh
+                }
+            };
+            let mem = region.as_mut_slice();
+            let prepared = setup(mem);
+
+            let faults_before = page_faults();
+            if timed {
+                counters.enable();
+            }
+            let start = clock.now();
+            f(mem, prepared);
+            let ticks = clock.now() - start;
+            if timed {
+                counters.disable();
+                faults += page_faults() - faults_before;
+            }
+
+            check(mem, prepared);
+            ticks
+        };
+
+        let warmup = Duration::from_millis(self.iterations.warmup_ms);
+        let warmup_start = Instant::now();
+        let mut warmup_ticks = Vec::new();
+        while warmup_start.elapsed() < warmup {
+            warmup_ticks.push(iteration(false));
+        }
+
+        let iters = match self.iterations.target_ms {
+            Some(target_ms) => {
+                // Without a warmup, a single untimed iteration has to do as the estimate
+                if warmup_ticks.is_empty() {
+                    warmup_ticks.push(iteration(false));
+                }
+                let ticks = warmup_ticks.iter().sum::<u64>() as f64 / warmup_ticks.len() as f64;
+                let estimate = ticks / clock.frequency();
+                let budget = target_ms as f64 / 1e3 / self.rounds as f64;
+                let iters = (budget / estimate).ceil();
+                (iters as u64).clamp(MIN_ITERS, MAX_ITERS)
+            }
+            None => iters,
+        };
+
+        let window = self.iterations.cv_window.max(2);
+        let mut samples = Vec::with_capacity(iters as usize);
+        for _ in 0..iters {
+            samples.push(iteration(true));
+
+            if let Some(threshold) = self.iterations.cv_threshold {
+                if samples.len() >= window {
+                    let recent = samples[samples.len() - window..]
+                        .iter()
+                        .map(|&ticks| ticks as f64)
+                        .collect::<Vec<_>>();
+                    if stats::cv(&recent) * 100.0 < threshold {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let freq = sampler.map(Sampler::stop);
+        let throttled = freq
+            .as_ref()
+            .is_some_and(|freq| freq.throttled(self.freq.min_fraction));
+
+        let measurement = Measurement {
+            name: name.to_string(),
+            size,
+            alloc: self.alloc,
+            pages: self.pages,
+            cpu: self.cpu,
+            core: self.core.clone(),
+            samples,
+            clock: self.clock.name(),
+            tick_frequency: self.clock.frequency(),
+            temp_start,
+            temp_end: self.cooldown.temperature(),
+            freq,
+            throttled,
+            counters: counters.read(),
+            faults,
+            slot: self.slot,
+        };
+
+        self.save(&measurement);
+        measurement
+    }
+
+    /// Waits for the device to cool down, then lets `run` take the samples itself with the
+    /// harness clock, for work that [`Harness::measure`] cannot time, such as a variant spread
+    /// over several threads.
+    ///
+    /// The measurement is labelled with `core` instead of the pinned cluster and has no frequency
+    /// trace, counters or page faults.
+    pub fn measure_external(
+        &mut self,
+        name: &str,
+        core: &str,
+        size: usize,
+        run: impl FnOnce(&dyn Clock) -> Vec<u64>,
+    ) -> Measurement {
+        self.cooldown.wait();
+
+        let temp_start = self.cooldown.temperature();
+        let samples = run(self.clock.as_ref());
+
+        let measurement = Measurement {
+            name: name.to_string(),
+            size,
+            alloc: self.alloc,
+            pages: self.pages,
+            cpu: None,
+            core: Some(core.to_string()),
+            samples,
+            clock: self.clock.name(),
+            tick_frequency: self.clock.frequency(),
+            temp_start,
+            temp_end: self.cooldown.temperature(),
+            freq: None,
+            throttled: false,
+            counters: Vec::new(),
+            faults: 0,
+            slot: self.slot,
+        };
+
+        self.save(&measurement);
+        measurement
+    }
+
+    /// Stores `measurement` if a results directory was given
+    fn save(&self, measurement: &Measurement) {
+        if let Some(store) = &self.store {
+            let record = Record::new(&store.run, &store.binary, &store.environment, measurement);
+            if let Err(e) = store.store.save(&record) {
+                eprintln!(
+                    "could not save results to {}: {}",
+                    store.store.root().display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        harness: HarnessConfig,
+    }
+
+    #[test]
+    fn defaults_match_the_command_line() {
+        let parsed = Cli::parse_from(["harness"]).harness;
+        let default = HarnessConfig::default();
+
+        // The configs have no `PartialEq`, but their `Debug` output lists every field
+        assert_eq!(
+            format!("{:?}", parsed.iterations),
+            format!("{:?}", default.iterations)
+        );
+        assert_eq!(
+            format!("{:?}", parsed.cooldown),
+            format!("{:?}", default.cooldown)
+        );
+        assert_eq!(format!("{:?}", parsed.freq), format!("{:?}", default.freq));
+        assert_eq!(parsed.clock, default.clock);
+        assert_eq!(parsed.alloc, default.alloc);
+        assert!(!default.no_validate);
+    }
+
+    #[test]
+    fn external_measurements_are_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Cli::parse_from([
+            "parallel",
+            "--clock",
+            "instant",
+            "--alloc",
+            "reused",
+            "--cooldown-fallback",
+            "0",
+            "--results-dir",
+            dir.path().to_str().unwrap(),
+            "--run-id",
+            "run",
+        ])
+        .harness;
+        config.cooldown.zones = vec!["no such zone".to_string()];
+        let mut harness = Harness::new(config);
+
+        let measurement = harness.measure_external("stg", "all-clusters-2threads", 4096, |clock| {
+            assert_eq!(clock.name(), "instant");
+            vec![1000, 3000]
+        });
+        assert_eq!(measurement.mean(), Duration::from_nanos(2000));
+        assert_eq!(measurement.throughput(), 4096.0 / 2e-6);
+
+        let records = ResultStore::new(dir.path()).load_run("run").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].core, "all-clusters-2threads");
+        assert_eq!(records[0].variant, "stg");
+        assert_eq!(records[0].alloc, "reused");
+        assert_eq!(records[0].samples_ns, [1000.0, 3000.0]);
+    }
+}
@@ -1,7 +1,26 @@
 use std::arch::asm;
 
+pub mod chunks;
+pub mod clock;
+pub mod cpu;
+pub mod fault;
+pub mod freq;
+pub mod harness;
+pub mod load;
+pub mod micro;
+pub mod perf;
+pub mod plan;
+pub mod plot;
+pub mod region;
+pub mod report;
+pub mod results;
+pub mod stats;
+pub mod thermal;
+pub mod variant;
+
+/// Returns `addr` with its tag replaced by the tag in bits 56-59 of `tag`
 #[inline]
-fn set_tag(addr: *mut u8, tag: u64) -> *mut u8 {
+pub fn set_tag(addr: *mut u8, tag: u64) -> *mut u8 {
     let tag = tag & 0x0f00_0000_0000_0000;
     (((addr as u64) & 0x0000_ffff_ffff_ffff) | tag) as *mut u8
 }
@@ -20,13 +39,10 @@ pub unsafe fn stg(mem: &mut [u8], tag: u64) {
 pub unsafe fn stg_prefetch(mem: &mut [u8], tag: u64) {
     debug_assert_eq!(mem.len() % 32, 0);
 
-    let index = mem.as_mut_ptr();
+    // `dc gzva` takes the tag from the address, unlike `stg`
+    let index = set_tag(mem.as_mut_ptr(), tag);
     let end = index.add(mem.len());
 
-    let line_size = 0u64;
-    let tmp = 0u64;
-    let next = 0u64;
-
     // The following code is a modified version of the code from the Android Scudo project
     // https://android.googlesource.com/platform/external/scudo/+/refs/tags/android-14.0.0_r1/standalone/memtag.h#167
     asm! {
@@ -71,10 +87,10 @@ pub unsafe fn stg_prefetch(mem: &mut [u8], tag: u64) {
 
         "5:",
 
-        tmp = in(reg) tmp,
-        line_size = in(reg) line_size,
-        next = in(reg) next,
-        index = in(reg) index,
+        tmp = out(reg) _,
+        line_size = out(reg) _,
+        next = out(reg) _,
+        index = inout(reg) index => _,
         end = in(reg) end,
         tag = in(reg) tag,
     };
@@ -107,6 +123,22 @@ pub unsafe fn stgp(mem: &mut [u8], tag: u64) {
     }
 }
 
+/// Sets the tags of `mem` to `tag` and fills it with `pattern`, one `stgp` per granule
+///
+/// # Safety
+///
+/// `mem` must be mapped with `PROT_MTE` and its length a multiple of 32 bytes.
+pub unsafe fn stgp_fill(mem: &mut [u8], tag: u64, pattern: u64) {
+    debug_assert_eq!(mem.len() % 32, 0);
+
+    let mut index = set_tag(mem.as_mut_ptr(), tag);
+    let end = index.add(mem.len());
+
+    while index != end {
+        asm!("stgp {pattern}, {pattern}, [{index}], #16", pattern = in(reg) pattern, index = inout(reg) index);
+    }
+}
+
 pub unsafe fn st2g(mem: &mut [u8], tag: u64) {
     debug_assert_eq!(mem.len() % 32, 0);
 
@@ -211,7 +243,8 @@ pub unsafe fn migrate_tags(from: &[u8], to: &mut [u8]) {
     let mut index_to = to.as_mut_ptr();
     let end = index.add(from.len());
 
-    while index != end {
+    // `ldg` replaces the tag of `index`, so compare the addresses without tags
+    while set_tag(index as *mut u8, 0) != set_tag(end as *mut u8, 0) {
         asm!(
             "ldg {index}, [{index}]",
             "ldg {index_to}, [{index}]",
@@ -226,6 +259,73 @@ pub unsafe fn migrate_tags(from: &[u8], to: &mut [u8]) {
     }
 }
 
+/// Copies `from` to `to` including tags like [`migrate_tags`], but stores the tags of two granules
+/// with a single `st2g` if they are equal and falls back to `stgp` for each granule otherwise
+///
+/// # Safety
+///
+/// Both slices must be mapped with `PROT_MTE` and aligned to 32 bytes.
+pub unsafe fn migrate_tags_st2g(from: &[u8], to: &mut [u8]) {
+    debug_assert_eq!(from.len() % 32, 0);
+    debug_assert!(to.len() >= from.len());
+
+    let mut index = from.as_ptr();
+    let mut index_to = to.as_mut_ptr();
+    let end = index.add(from.len());
+
+    while set_tag(index as *mut u8, 0) != set_tag(end as *mut u8, 0) {
+        asm!(
+            "add {second}, {index}, #16",
+            "ldg {index}, [{index}]",
+            "ldg {second}, [{second}]",
+            "ldp {val1}, {val2}, [{index}]",
+            "ldp {val3}, {val4}, [{second}]",
+            "ubfx {tag1}, {index}, #56, #4",
+            "ubfx {tag2}, {second}, #56, #4",
+            "bfi {index_to}, {tag1}, #56, #4",
+            "cmp {tag1}, {tag2}",
+            "b.ne 2f",
+            "st2g {index_to}, [{index_to}]",
+            "stp {val1}, {val2}, [{index_to}]",
+            "stp {val3}, {val4}, [{index_to}, #16]",
+            "b 3f",
+            "2:",
+            "stgp {val1}, {val2}, [{index_to}]",
+            "bfi {index_to}, {tag2}, #56, #4",
+            "stgp {val3}, {val4}, [{index_to}, #16]",
+            "3:",
+            "add {index}, {index}, #32",
+            "add {index_to}, {index_to}, #32",
+            index = inout(reg) index,
+            index_to = inout(reg) index_to,
+            second = out(reg) _,
+            tag1 = out(reg) _,
+            tag2 = out(reg) _,
+            val1 = out(reg) _,
+            val2 = out(reg) _,
+            val3 = out(reg) _,
+            val4 = out(reg) _,
+            options(nostack),
+        );
+    }
+}
+
+/// Loads the tag of the granule containing `addr`, returned in bits 56-59
+///
+/// # Safety
+///
+/// `addr` must point into memory mapped with `PROT_MTE`.
+pub unsafe fn load_tag(addr: *const u8) -> u64 {
+    let tagged: u64;
+    asm!(
+        "ldg {tagged}, [{addr}]",
+        tagged = inout(reg) 0u64 => tagged,
+        addr = in(reg) addr,
+        options(readonly, nostack),
+    );
+    tagged & 0x0f00_0000_0000_0000
+}
+
 /// In which mode MTE should be enabled
 #[derive(Copy, Clone)]
 pub enum MTEMode {
@@ -0,0 +1,148 @@
+use std::hint::black_box;
+
+use clap::ValueEnum;
+use rand::random;
+use rand::seq::SliceRandom;
+
+use crate::{set_tag, stg};
+
+/// Distance between two reads of [`Workload::Strided`] and size of a node of [`Workload::Chase`]
+const STRIDE: usize = 64;
+
+const TAG_MASK: u64 = 0x0f00_0000_0000_0000;
+
+/// Memory accesses performed on tagged memory, to compare tag checks on loads with stores
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Workload {
+    /// Zero the memory, the store-only baseline
+    Memset,
+    /// Sum all 64-bit words in order
+    Sum,
+    /// Read one 64-bit word per cache line
+    Strided,
+    /// Follow a linked list with one node per cache line in random order
+    Chase,
+    /// Copy the first half of the memory to the second half, each half with its own tag
+    Memcpy,
+}
+
+impl Workload {
+    pub const ALL: [Workload; 5] = [
+        Workload::Memset,
+        Workload::Sum,
+        Workload::Strided,
+        Workload::Chase,
+        Workload::Memcpy,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Workload::Memset => "memset",
+            Workload::Sum => "sum",
+            Workload::Strided => "strided",
+            Workload::Chase => "chase",
+            Workload::Memcpy => "memcpy",
+        }
+    }
+
+    /// Tags `mem` with a random tag and writes the data the workload reads. Returns the tag.
+    ///
+    /// [`Workload::Memset`] leaves `mem` untouched with tag 0, as it was measured before the other
+    /// workloads existed, so that its page faults stay in the timed section and its results remain
+    /// comparable with earlier ones.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be mapped with `PROT_MTE` and its length a multiple of 64 bytes.
+    pub unsafe fn prepare(self, mem: &mut [u8]) -> u64 {
+        if self == Workload::Memset {
+            return 0;
+        }
+
+        let tag = random::<u64>() & TAG_MASK;
+
+        match self {
+            Workload::Memcpy => {
+                let (src, dst) = mem.split_at_mut(mem.len() / 2);
+                stg(src, tag);
+                stg(dst, next_tag(tag));
+            }
+            _ => stg(mem, tag),
+        }
+
+        if self == Workload::Chase {
+            build_chain(tagged(mem, tag));
+        }
+
+        tag
+    }
+
+    /// Runs the workload on memory prepared by [`Workload::prepare`], accessing it through
+    /// pointers with `tag`
+    ///
+    /// # Safety
+    ///
+    /// `mem` must have been prepared for this workload with `tag`.
+    pub unsafe fn run(self, mem: &mut [u8], tag: u64) {
+        match self {
+            Workload::Memset => tagged(mem, tag).fill(0),
+            Workload::Sum => {
+                let sum = words(tagged(mem, tag))
+                    .iter()
+                    .fold(0u64, |sum, &word| sum.wrapping_add(word));
+                black_box(sum);
+            }
+            Workload::Strided => {
+                let sum = words(tagged(mem, tag))
+                    .iter()
+                    .step_by(STRIDE / 8)
+                    .fold(0u64, |sum, &word| sum.wrapping_add(word));
+                black_box(sum);
+            }
+            Workload::Chase => {
+                let mut node = set_tag(mem.as_mut_ptr(), tag) as *const usize;
+                for _ in 0..mem.len() / STRIDE {
+                    node = node.read_volatile() as *const usize;
+                }
+                black_box(node);
+            }
+            Workload::Memcpy => {
+                let (src, dst) = mem.split_at_mut(mem.len() / 2);
+                tagged(dst, next_tag(tag)).copy_from_slice(tagged(src, tag));
+            }
+        }
+    }
+}
+
+/// A different tag than `tag`, for the destination of [`Workload::Memcpy`]
+fn next_tag(tag: u64) -> u64 {
+    tag.wrapping_add(1 << 56) & TAG_MASK
+}
+
+unsafe fn tagged(mem: &mut [u8], tag: u64) -> &mut [u8] {
+    std::slice::from_raw_parts_mut(set_tag(mem.as_mut_ptr(), tag), mem.len())
+}
+
+fn words(mem: &[u8]) -> &[u64] {
+    unsafe { std::slice::from_raw_parts(mem.as_ptr() as *const u64, mem.len() / 8) }
+}
+
+/// Links the cache lines of `mem` into a cycle in random order, starting at the first line.
+///
+/// Every line starts with the (tagged) address of the next line.
+fn build_chain(mem: &mut [u8]) {
+    let base = mem.as_mut_ptr();
+    let nodes = mem.len() / STRIDE;
+
+    let mut order = (1..nodes).collect::<Vec<_>>();
+    order.shuffle(&mut rand::thread_rng());
+    order.insert(0, 0);
+
+    for (i, &node) in order.iter().enumerate() {
+        let next = order[(i + 1) % nodes];
+        unsafe {
+            let node = base.add(node * STRIDE) as *mut usize;
+            node.write(base.add(next * STRIDE) as usize);
+        }
+    }
+}
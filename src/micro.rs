@@ -0,0 +1,359 @@
+//! Kernels timing single MTE instructions.
+//!
+//! Every kernel runs `rounds` rounds of [`OPS`] instructions. Latency kernels form a single
+//! dependency chain, where every instruction consumes the result of the previous one. Throughput
+//! kernels interleave [`OPS`] independent chains, so consecutive instructions can execute in
+//! parallel.
+//!
+//! The tag store kernels measure how soon freshly tagged memory can be used: their latency kernels
+//! retag a granule, store the new tag and immediately load through the new pointer, making the
+//! address of the next store depend on the loaded value. Their throughput kernels tag
+//! [`STORE_SIZE`] bytes per round with a given number of stores per loop iteration.
+
+use std::arch::asm;
+use std::hint::black_box;
+
+use crate::set_tag;
+
+/// Instructions executed per round
+pub const OPS: u64 = 8;
+
+/// Bytes tagged per round by the tag store throughput kernels
+pub const STORE_SIZE: usize = 4096;
+
+/// Bytes of zeroed `PROT_MTE` memory the kernels expect
+pub const MEM_SIZE: usize = STORE_SIZE;
+
+/// Sets of tags `irg` may generate, passed to [`crate::set_mte_mode_tags`]
+pub const IRG_MASKS: [(&str, u64); 4] = [
+    ("all", 0xffff),
+    ("nonzero", 0xfffe),
+    ("half", 0x00ff),
+    ("two", 0x0006),
+];
+
+pub(crate) unsafe fn irg_latency(mem: *mut u8, rounds: u64) {
+    let mut x = mem as u64;
+    for _ in 0..rounds {
+        asm!(
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            "irg {x}, {x}",
+            x = inout(reg) x,
+            options(nomem, nostack),
+        );
+    }
+    black_box(x);
+}
+
+pub(crate) unsafe fn irg_throughput(mem: *mut u8, rounds: u64) {
+    let mut x = [mem as u64; OPS as usize];
+    for _ in 0..rounds {
+        asm!(
+            "irg {a}, {a}",
+            "irg {b}, {b}",
+            "irg {c}, {c}",
+            "irg {d}, {d}",
+            "irg {e}, {e}",
+            "irg {f}, {f}",
+            "irg {g}, {g}",
+            "irg {h}, {h}",
+            a = inout(reg) x[0],
+            b = inout(reg) x[1],
+            c = inout(reg) x[2],
+            d = inout(reg) x[3],
+            e = inout(reg) x[4],
+            f = inout(reg) x[5],
+            g = inout(reg) x[6],
+            h = inout(reg) x[7],
+            options(nomem, nostack),
+        );
+    }
+    black_box(x);
+}
+
+pub(crate) unsafe fn addg_latency(mem: *mut u8, rounds: u64) {
+    let mut x = mem as u64;
+    for _ in 0..rounds {
+        asm!(
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            "addg {x}, {x}, #16, #1",
+            x = inout(reg) x,
+            options(nomem, nostack),
+        );
+    }
+    black_box(x);
+}
+
+pub(crate) unsafe fn addg_throughput(mem: *mut u8, rounds: u64) {
+    let mut x = [mem as u64; OPS as usize];
+    for _ in 0..rounds {
+        asm!(
+            "addg {a}, {a}, #16, #1",
+            "addg {b}, {b}, #16, #1",
+            "addg {c}, {c}, #16, #1",
+            "addg {d}, {d}, #16, #1",
+            "addg {e}, {e}, #16, #1",
+            "addg {f}, {f}, #16, #1",
+            "addg {g}, {g}, #16, #1",
+            "addg {h}, {h}, #16, #1",
+            a = inout(reg) x[0],
+            b = inout(reg) x[1],
+            c = inout(reg) x[2],
+            d = inout(reg) x[3],
+            e = inout(reg) x[4],
+            f = inout(reg) x[5],
+            g = inout(reg) x[6],
+            h = inout(reg) x[7],
+            options(nomem, nostack),
+        );
+    }
+    black_box(x);
+}
+
+pub(crate) unsafe fn subg_latency(mem: *mut u8, rounds: u64) {
+    let mut x = mem as u64;
+    for _ in 0..rounds {
+        asm!(
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            "subg {x}, {x}, #16, #1",
+            x = inout(reg) x,
+            options(nomem, nostack),
+        );
+    }
+    black_box(x);
+}
+
+pub(crate) unsafe fn subg_throughput(mem: *mut u8, rounds: u64) {
+    let mut x = [mem as u64; OPS as usize];
+    for _ in 0..rounds {
+        asm!(
+            "subg {a}, {a}, #16, #1",
+            "subg {b}, {b}, #16, #1",
+            "subg {c}, {c}, #16, #1",
+            "subg {d}, {d}, #16, #1",
+            "subg {e}, {e}, #16, #1",
+            "subg {f}, {f}, #16, #1",
+            "subg {g}, {g}, #16, #1",
+            "subg {h}, {h}, #16, #1",
+            a = inout(reg) x[0],
+            b = inout(reg) x[1],
+            c = inout(reg) x[2],
+            d = inout(reg) x[3],
+            e = inout(reg) x[4],
+            f = inout(reg) x[5],
+            g = inout(reg) x[6],
+            h = inout(reg) x[7],
+            options(nomem, nostack),
+        );
+    }
+    black_box(x);
+}
+
+/// Chains through the exclusion mask, which `gmi` both reads and writes
+pub(crate) unsafe fn gmi_latency(mem: *mut u8, rounds: u64) {
+    let mut mask = 0u64;
+    for _ in 0..rounds {
+        asm!(
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            "gmi {m}, {p}, {m}",
+            m = inout(reg) mask,
+            p = in(reg) mem as u64,
+            options(nomem, nostack),
+        );
+    }
+    black_box(mask);
+}
+
+pub(crate) unsafe fn gmi_throughput(mem: *mut u8, rounds: u64) {
+    let mut mask = [0u64; OPS as usize];
+    for _ in 0..rounds {
+        asm!(
+            "gmi {a}, {p}, {a}",
+            "gmi {b}, {p}, {b}",
+            "gmi {c}, {p}, {c}",
+            "gmi {d}, {p}, {d}",
+            "gmi {e}, {p}, {e}",
+            "gmi {f}, {p}, {f}",
+            "gmi {g}, {p}, {g}",
+            "gmi {h}, {p}, {h}",
+            a = inout(reg) mask[0],
+            b = inout(reg) mask[1],
+            c = inout(reg) mask[2],
+            d = inout(reg) mask[3],
+            e = inout(reg) mask[4],
+            f = inout(reg) mask[5],
+            g = inout(reg) mask[6],
+            h = inout(reg) mask[7],
+            p = in(reg) mem as u64,
+            options(nomem, nostack),
+        );
+    }
+    black_box(mask);
+}
+
+/// Loads the tag of the same granule through the pointer the previous `ldg` produced
+pub(crate) unsafe fn ldg_latency(mem: *mut u8, rounds: u64) {
+    let mut x = mem as u64;
+    for _ in 0..rounds {
+        asm!(
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            "ldg {x}, [{x}]",
+            x = inout(reg) x,
+            options(readonly, nostack),
+        );
+    }
+    black_box(x);
+}
+
+/// Loads the tags of [`OPS`] different granules
+pub(crate) unsafe fn ldg_throughput(mem: *mut u8, rounds: u64) {
+    let mut x: [u64; OPS as usize] = std::array::from_fn(|i| mem.add(16 * i) as u64);
+    for _ in 0..rounds {
+        asm!(
+            "ldg {a}, [{a}]",
+            "ldg {b}, [{b}]",
+            "ldg {c}, [{c}]",
+            "ldg {d}, [{d}]",
+            "ldg {e}, [{e}]",
+            "ldg {f}, [{f}]",
+            "ldg {g}, [{g}]",
+            "ldg {h}, [{h}]",
+            a = inout(reg) x[0],
+            b = inout(reg) x[1],
+            c = inout(reg) x[2],
+            d = inout(reg) x[3],
+            e = inout(reg) x[4],
+            f = inout(reg) x[5],
+            g = inout(reg) x[6],
+            h = inout(reg) x[7],
+            options(readonly, nostack),
+        );
+    }
+    black_box(x);
+}
+
+/// Defines a latency kernel for a tag store instruction, given as the template of a store to
+/// `[{p}]` that takes the tag from `{p}`.
+///
+/// The loaded value is always zero, so adding it only serves to make the next round wait for the
+/// load.
+macro_rules! tag_store_latency {
+    ($name:ident, $store:literal) => {
+        pub(crate) unsafe fn $name(mem: *mut u8, rounds: u64) {
+            let mut p = mem as u64;
+            for _ in 0..rounds {
+                asm!(
+                    "addg {p}, {p}, #0, #1",
+                    $store,
+                    "ldr {t}, [{p}]",
+                    "add {p}, {p}, {t}",
+                    p = inout(reg) p,
+                    t = out(reg) _,
+                    options(nostack),
+                );
+            }
+            black_box(p);
+        }
+    };
+}
+
+tag_store_latency!(stg_latency, "stg {p}, [{p}]");
+tag_store_latency!(st2g_latency, "st2g {p}, [{p}]");
+tag_store_latency!(stzg_latency, "stzg {p}, [{p}]");
+tag_store_latency!(stz2g_latency, "stz2g {p}, [{p}]");
+tag_store_latency!(stgp_latency, "stgp xzr, xzr, [{p}]");
+
+/// Defines a throughput kernel storing tags at the given offsets from `{index}` in every loop
+/// iteration, given the template of a store to `[{index}, #offset]` that takes the tag from
+/// `{index}`
+macro_rules! tag_store_throughput {
+    ($name:ident, $store:literal, $granule:literal, [$($offset:literal),+]) => {
+        pub(crate) unsafe fn $name(mem: *mut u8, rounds: u64) {
+            let step = $granule * [$($offset),+].len();
+            let start = set_tag(mem, 1 << 56);
+            for _ in 0..rounds {
+                let mut index = start;
+                let end = start.add(STORE_SIZE);
+                while index != end {
+                    asm!(
+                        $(concat!($store, " [{index}, #", $offset, "]"),)+
+                        index = in(reg) index,
+                        options(nostack),
+                    );
+                    index = index.add(step);
+                }
+            }
+        }
+    };
+}
+
+tag_store_throughput!(stg_x1, "stg {index},", 16, [0]);
+tag_store_throughput!(stg_x2, "stg {index},", 16, [0, 16]);
+tag_store_throughput!(stg_x4, "stg {index},", 16, [0, 16, 32, 48]);
+tag_store_throughput!(stg_x8, "stg {index},", 16, [0, 16, 32, 48, 64, 80, 96, 112]);
+tag_store_throughput!(st2g_x1, "st2g {index},", 32, [0]);
+tag_store_throughput!(st2g_x2, "st2g {index},", 32, [0, 32]);
+tag_store_throughput!(st2g_x4, "st2g {index},", 32, [0, 32, 64, 96]);
+tag_store_throughput!(
+    st2g_x8,
+    "st2g {index},",
+    32,
+    [0, 32, 64, 96, 128, 160, 192, 224]
+);
+tag_store_throughput!(stzg_x1, "stzg {index},", 16, [0]);
+tag_store_throughput!(stzg_x2, "stzg {index},", 16, [0, 16]);
+tag_store_throughput!(stzg_x4, "stzg {index},", 16, [0, 16, 32, 48]);
+tag_store_throughput!(
+    stzg_x8,
+    "stzg {index},",
+    16,
+    [0, 16, 32, 48, 64, 80, 96, 112]
+);
+tag_store_throughput!(stz2g_x1, "stz2g {index},", 32, [0]);
+tag_store_throughput!(stz2g_x2, "stz2g {index},", 32, [0, 32]);
+tag_store_throughput!(stz2g_x4, "stz2g {index},", 32, [0, 32, 64, 96]);
+tag_store_throughput!(
+    stz2g_x8,
+    "stz2g {index},",
+    32,
+    [0, 32, 64, 96, 128, 160, 192, 224]
+);
+tag_store_throughput!(stgp_x1, "stgp xzr, xzr,", 16, [0]);
+tag_store_throughput!(stgp_x2, "stgp xzr, xzr,", 16, [0, 16]);
+tag_store_throughput!(stgp_x4, "stgp xzr, xzr,", 16, [0, 16, 32, 48]);
+tag_store_throughput!(
+    stgp_x8,
+    "stgp xzr, xzr,",
+    16,
+    [0, 16, 32, 48, 64, 80, 96, 112]
+);
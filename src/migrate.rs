@@ -1,67 +1,216 @@
-use mte_measurement::{migrate_tags, set_mte_mode, set_tags_random, MTEMode};
+use clap::Parser;
+use mte_measurement::clock::{Clock, ClockKind};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::region::Region;
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use mte_measurement::{
+    load_tag, migrate_mte_off, migrate_tags, migrate_tags_st2g, set_mte_mode, set_tag,
+    set_tags_random, stg, MTEMode,
+};
 use rand::random;
 use std::hint::black_box;
+use std::time::Duration;
 
-const SIZE: usize = 512;
-
-fn measure_custom(iters: u64, f: impl Fn(&[u8], &mut [u8]) -> ()) -> std::time::Duration {
-    let mut result = std::time::Duration::from_secs(0);
-
-    let mut mem = unsafe {
-        libc::mmap(
-            std::ptr::null_mut(),
-            SIZE,
-            libc::PROT_READ | libc::PROT_WRITE | 0x20,
-            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-            -1,
-            0,
-        )
-    };
-    assert_ne!(mem, libc::MAP_FAILED);
-    unsafe {
-        // fill with random values
-        for i in 0..SIZE {
-            std::ptr::write(mem.add(i) as *mut u8, random());
-        }
-        set_tags_random(std::slice::from_raw_parts_mut(mem as *mut u8, SIZE));
+#[derive(Parser)]
+struct Args {
+    /// Sizes to copy in bytes, comma separated; must be multiples of 32
+    #[arg(long, value_delimiter = ',', default_values_t = [512, 4096, 65536, 1 << 20, 16 << 20])]
+    sizes: Vec<usize>,
+    /// Timed samples per strategy and size
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    iters: u32,
+    /// Copies smaller than this many bytes are repeated within a sample until they reach it
+    #[arg(long, default_value_t = 1 << 20)]
+    min_bytes: usize,
+    /// Time source used to time each sample
+    #[arg(long, value_enum, default_value_t = ClockKind::Auto)]
+    clock: ClockKind,
+    #[command(flatten)]
+    cooldown: CooldownConfig,
+}
+
+/// How the tags of the source are laid out
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Tags {
+    /// Every granule has a different tag than its neighbours
+    Granule,
+    /// The whole source has one tag
+    Uniform,
+}
+
+struct Strategy {
+    name: &'static str,
+    /// Whether the strategy copies tags, otherwise the source is untagged
+    copies_tags: bool,
+    f: unsafe fn(&[u8], &mut [u8]),
+}
+
+unsafe fn memcpy(from: &[u8], to: &mut [u8]) {
+    to[..from.len()].copy_from_slice(from);
+}
+
+const STRATEGIES: [Strategy; 4] = [
+    Strategy {
+        name: "memcpy",
+        copies_tags: false,
+        f: memcpy,
+    },
+    Strategy {
+        name: "migrate_tags",
+        copies_tags: true,
+        f: migrate_tags,
+    },
+    Strategy {
+        name: "migrate_mte_off",
+        copies_tags: true,
+        f: migrate_mte_off,
+    },
+    Strategy {
+        name: "migrate_tags_st2g",
+        copies_tags: true,
+        f: migrate_tags_st2g,
+    },
+];
+
+/// Maps `size` bytes of random data, tagged according to `tags` or left untagged
+fn source(size: usize, tags: Option<Tags>) -> Region {
+    let mut region = Region::map(size, 0).expect("could not map memory");
+    let mem = region.as_mut_slice();
+    mem.iter_mut().for_each(|byte| *byte = random());
+
+    match tags {
+        Some(Tags::Granule) => unsafe { set_tags_random(mem) },
+        Some(Tags::Uniform) => unsafe { stg(mem, random()) },
+        None => {}
     }
 
-    for _ in 0..iters {
-        let new_mem = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                SIZE,
-                libc::PROT_READ | libc::PROT_WRITE | 0x20,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-                -1,
-                0,
+    region
+}
+
+/// Checks that `to` holds the data of `from` and, if `tags` is set, the same tags
+fn verify(from: &[u8], to: &[u8], tags: bool) -> Result<(), String> {
+    for offset in (0..from.len()).step_by(16) {
+        let (from, to) = (from[offset..].as_ptr(), to[offset..].as_ptr());
+        let (tag, to_tag) = unsafe { (load_tag(from), load_tag(to)) };
+
+        if tags && tag != to_tag {
+            return Err(format!(
+                "tag {:#x} instead of {:#x} at offset {}",
+                to_tag >> 56,
+                tag >> 56,
+                offset
+            ));
+        }
+
+        let (expected, actual) = unsafe {
+            (
+                std::slice::from_raw_parts(set_tag(from as *mut u8, tag), 16),
+                std::slice::from_raw_parts(set_tag(to as *mut u8, to_tag), 16),
             )
         };
-        assert_ne!(new_mem, libc::MAP_FAILED);
+        if expected != actual {
+            return Err(format!("data differs at offset {}", offset));
+        }
+    }
 
-        let mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
-        let mut new_mem_slice = unsafe { std::slice::from_raw_parts_mut(new_mem as *mut u8, SIZE) };
+    Ok(())
+}
 
-        let start = std::time::Instant::now();
-        f(&mem_slice, &mut new_mem_slice);
-        result += start.elapsed();
+/// Times `iters` samples of copying `from` to a fresh, pre-faulted mapping, verifying every copy
+fn measure_custom(
+    clock: &dyn Clock,
+    iters: u32,
+    reps: usize,
+    from: &[u8],
+    strategy: &Strategy,
+) -> Vec<Duration> {
+    (0..iters)
+        .map(|_| {
+            let mut to = Region::map(from.len(), 0).expect("could not map memory");
+            to.touch();
+            let to = to.as_mut_slice();
 
-        unsafe { libc::munmap(mem, SIZE) };
-        mem = new_mem;
-    }
+            let start = clock.now();
+            for _ in 0..reps {
+                unsafe { (strategy.f)(black_box(from), black_box(&mut *to)) };
+            }
+            let elapsed = clock.to_duration(clock.now() - start) / reps as u32;
 
-    unsafe { libc::munmap(mem, SIZE) };
+            if let Err(e) = verify(from, to, strategy.copies_tags) {
+                panic!(
+                    "{} copied {} bytes incorrectly: {}",
+                    strategy.name,
+                    from.len(),
+                    e
+                );
+            }
 
-    result
+            elapsed
+        })
+        .collect()
 }
 
 fn main() {
+    let args = Args::parse();
+
+    if let Some(size) = args.sizes.iter().find(|&&size| size == 0 || size % 32 != 0) {
+        eprintln!("size {} is not a positive multiple of 32 bytes", size);
+        std::process::exit(1);
+    }
+
     unsafe {
         set_mte_mode(MTEMode::Sync);
     }
 
-    measure_custom(black_box(1), |from, to| unsafe {
-        migrate_tags(from, to);
-    });
-    println!("Done!");
+    let clock = args.clock.create().expect("could not create clock");
+    let cooldown = Cooldown::new(args.cooldown);
+
+    for cluster in discover_clusters() {
+        pin_to_cpu(cluster.representative()).expect("could not pin to cpu");
+
+        let mut table = format!("{:30}", "strategy");
+        for size in &args.sizes {
+            table += &format!(" {:>12}", format!("{} B", size));
+        }
+
+        for strategy in &STRATEGIES {
+            let patterns = if strategy.copies_tags {
+                vec![Some(Tags::Granule), Some(Tags::Uniform)]
+            } else {
+                vec![None]
+            };
+
+            for tags in patterns {
+                let name = match tags {
+                    Some(tags) => format!("{} ({:?} tags)", strategy.name, tags).to_lowercase(),
+                    None => strategy.name.to_string(),
+                };
+                table += &format!("\n{:30}", name);
+
+                for &size in &args.sizes {
+                    let mut from = source(size, tags);
+                    let reps = (args.min_bytes / size).max(1);
+                    cooldown.wait();
+
+                    let samples = measure_custom(
+                        clock.as_ref(),
+                        args.iters,
+                        reps,
+                        from.as_mut_slice(),
+                        strategy,
+                    );
+                    let mean = samples.iter().sum::<Duration>() / args.iters;
+                    let throughput = size as f64 / mean.as_secs_f64() / 1e9;
+
+                    println!(
+                        "{} {} with {} bytes: {:.2?} ({:.2} GB/s)",
+                        cluster, name, size, mean, throughput
+                    );
+                    table += &format!(" {:>12}", format!("{:.2} GB/s", throughput));
+                }
+            }
+        }
+
+        println!("{}:\n{}", cluster, table);
+    }
 }
@@ -0,0 +1,221 @@
+use clap::Parser;
+use mte_measurement::clock::{Clock, ClockKind};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use mte_measurement::{set_mte_mode, MTEMode};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+
+const MODES: [(&str, MTEMode); 3] = [
+    ("none", MTEMode::None),
+    ("sync", MTEMode::Sync),
+    ("async", MTEMode::Async),
+];
+
+#[derive(Parser)]
+struct Args {
+    /// `prctl` calls per mode switch sample, rounded down to an even number
+    #[arg(long, default_value_t = 100_000, value_parser = clap::value_parser!(u32).range(2..))]
+    switches: u32,
+    /// Round trips per ping-pong sample
+    #[arg(long, default_value_t = 100_000, value_parser = clap::value_parser!(u32).range(1..))]
+    round_trips: u32,
+    /// Timed samples per measurement
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    iters: u32,
+    /// Time source used to time each sample
+    #[arg(long, value_enum, default_value_t = ClockKind::Auto)]
+    clock: ClockKind,
+    #[command(flatten)]
+    cooldown: CooldownConfig,
+}
+
+/// Both ends of a pipe, closed on drop
+struct Pipe {
+    read: i32,
+    write: i32,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        let mut fds = [0; 2];
+        assert_eq!(
+            unsafe { libc::pipe(fds.as_mut_ptr()) },
+            0,
+            "could not create pipe"
+        );
+        Pipe {
+            read: fds[0],
+            write: fds[1],
+        }
+    }
+
+    fn send(&self) {
+        let byte = 0u8;
+        let written = unsafe { libc::write(self.write, &byte as *const u8 as *const _, 1) };
+        assert_eq!(written, 1, "could not write to pipe");
+    }
+
+    fn receive(&self) {
+        let mut byte = 0u8;
+        let read = unsafe { libc::read(self.read, &mut byte as *mut u8 as *mut _, 1) };
+        assert_eq!(read, 1, "could not read from pipe");
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+/// Time per `prctl` call when alternating between `from` and `to`
+fn mode_switch(clock: &dyn Clock, from: MTEMode, to: MTEMode, switches: u32) -> Duration {
+    let start = clock.now();
+    for _ in 0..switches / 2 {
+        unsafe {
+            set_mte_mode(to);
+            set_mte_mode(from);
+        }
+    }
+    clock.to_duration(clock.now() - start) / (switches / 2 * 2)
+}
+
+/// Answers `round_trips` pings on `ping` with a pong on `pong`
+fn echo(ping: &Pipe, pong: &Pipe, round_trips: u32) {
+    for _ in 0..round_trips {
+        ping.receive();
+        pong.send();
+    }
+}
+
+/// Time per round trip when pinging `round_trips` times
+fn ping(clock: &dyn Clock, ping: &Pipe, pong: &Pipe, round_trips: u32) -> Duration {
+    let start = clock.now();
+    for _ in 0..round_trips {
+        ping.send();
+        pong.receive();
+    }
+    clock.to_duration(clock.now() - start) / round_trips
+}
+
+/// Round trip time to a second thread pinned to the same CPU, both running in `mode`
+fn ping_pong_thread(clock: &dyn Clock, cpu: usize, mode: MTEMode, round_trips: u32) -> Duration {
+    let (pings, pongs) = (Pipe::new(), Pipe::new());
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            pin_to_cpu(cpu).expect("could not pin to cpu");
+            unsafe {
+                set_mte_mode(mode);
+            }
+            echo(&pings, &pongs, round_trips);
+        });
+
+        ping(clock, &pings, &pongs, round_trips)
+    })
+}
+
+/// Round trip time to a child process pinned to the same CPU, both running in `mode`
+fn ping_pong_process(clock: &dyn Clock, mode: MTEMode, round_trips: u32) -> Duration {
+    let (pings, pongs) = (Pipe::new(), Pipe::new());
+
+    // The child inherits the CPU affinity of the calling thread
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "could not fork");
+    if pid == 0 {
+        // Unwinding out of here would run the parent's code in the child, so exit on panics too
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            unsafe {
+                set_mte_mode(mode);
+            }
+            echo(&pings, &pongs, round_trips);
+        }));
+        unsafe { libc::_exit(result.is_err() as i32) };
+    }
+
+    let elapsed = ping(clock, &pings, &pongs, round_trips);
+
+    let mut status = 0;
+    assert_eq!(
+        unsafe { libc::waitpid(pid, &mut status, 0) },
+        pid,
+        "could not wait for child"
+    );
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "child exited with status {}",
+        status
+    );
+
+    elapsed
+}
+
+fn mean(samples: impl Iterator<Item = Duration>, iters: u32) -> Duration {
+    samples.sum::<Duration>() / iters
+}
+
+fn main() {
+    let args = Args::parse();
+    let clock = args.clock.create().expect("could not create clock");
+    let cooldown = Cooldown::new(args.cooldown);
+
+    for cluster in discover_clusters() {
+        let cpu = cluster.representative();
+        pin_to_cpu(cpu).expect("could not pin to cpu");
+
+        let mut table = format!("{:12}", "from \\ to");
+        for (to, _) in MODES {
+            table += &format!(" {:>10}", to);
+        }
+        for (from_name, from) in MODES {
+            table += &format!("\n{:12}", from_name);
+            for (to_name, to) in MODES {
+                cooldown.wait();
+                unsafe {
+                    set_mte_mode(from);
+                }
+                let switch = mean(
+                    (0..args.iters).map(|_| mode_switch(clock.as_ref(), from, to, args.switches)),
+                    args.iters,
+                );
+                println!(
+                    "{} switch {} -> {}: {:.2?}",
+                    cluster, from_name, to_name, switch
+                );
+                table += &format!(" {:>10}", format!("{:.2?}", switch));
+            }
+        }
+        println!("{} prctl latency:\n{}", cluster, table);
+
+        let mut table = format!("{:12}", "round trip");
+        for (name, _) in MODES {
+            table += &format!(" {:>10}", name);
+        }
+        for (kind, process) in [("threads", false), ("processes", true)] {
+            table += &format!("\n{:12}", kind);
+            for (name, mode) in MODES {
+                cooldown.wait();
+                unsafe {
+                    set_mte_mode(mode);
+                }
+                let round_trip = mean(
+                    (0..args.iters).map(|_| match process {
+                        true => ping_pong_process(clock.as_ref(), mode, args.round_trips),
+                        false => ping_pong_thread(clock.as_ref(), cpu, mode, args.round_trips),
+                    }),
+                    args.iters,
+                );
+                println!(
+                    "{} {} ping-pong in {} mode: {:.2?}",
+                    cluster, kind, name, round_trip
+                );
+                table += &format!(" {:>10}", format!("{:.2?}", round_trip));
+            }
+        }
+        println!("{} pipe ping-pong on cpu {}:\n{}", cluster, cpu, table);
+    }
+}
@@ -1,63 +1,88 @@
 use std::hint::black_box;
-use mte_measurement::{memset, MTEMode, set_mte_mode};
+use std::time::Duration;
+use clap::Parser;
+use mte_measurement::cpu::discover_clusters;
+use mte_measurement::harness::{throughput_table, Harness, HarnessConfig};
+use mte_measurement::load::Workload;
+use mte_measurement::plan::{Plan, PlanConfig};
+use mte_measurement::{MTEMode, set_mte_mode};
 
 // 128 MiB
 const SIZE: usize = 128 * 1024 * 1024;
 
-const ITERS: u32 = 50;
+const ITERS: u64 = 50;
 
-fn measure_custom(iters: u64, mode: MTEMode, f: impl Fn(&mut [u8]) -> ()) -> std::time::Duration {
-    let mut result = std::time::Duration::from_secs(0);
-
-    unsafe {
-        set_mte_mode(mode);
-    }
-
-    for _ in 0..iters {
-        let mem = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                SIZE,
-                libc::PROT_READ | libc::PROT_WRITE | 0x20,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-                -1,
-                0,
-            )
-        };
-
-        assert_ne!(mem, libc::MAP_FAILED);
-        let mut mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
-
-        let start = std::time::Instant::now();
-        f(&mut mem_slice);
-        result += start.elapsed();
-
-        unsafe { libc::munmap(mem, SIZE) };
-    }
-
-    result
+#[derive(Parser)]
+struct Args {
+    /// Workloads to run under every mode, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = Workload::ALL)]
+    workload: Vec<Workload>,
+    #[command(flatten)]
+    harness: HarnessConfig,
+    #[command(flatten)]
+    plan: PlanConfig,
 }
 
 fn main() {
+    let args = Args::parse();
+
     let modes = [
-        MTEMode::None,
-        MTEMode::Sync,
-        MTEMode::Async,
+        ("none", MTEMode::None),
+        ("sync", MTEMode::Sync),
+        ("async", MTEMode::Async),
     ];
 
-    let mut results = Vec::new();
+    // The same order of modes is used for every workload
+    let plan = Plan::new(&args.plan, modes.len());
+    let names = modes.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    println!("mode order {}", plan.describe(&names));
 
-    for mode in modes {
-        let result = measure_custom(ITERS.into(), mode, |mem| unsafe {
-            memset(black_box(mem));
-        });
-        results.push(result / ITERS);
-        std::thread::sleep(std::time::Duration::from_secs(15));
-    }
+    let workloads = args.workload;
+    let mut harness = Harness::new(args.harness);
+    harness.set_rounds(plan.rounds.len());
+    let page_policies = harness.page_policies();
 
-    let result = results.iter().map(|result| result.as_millis())
-        .map(|m| m.to_string())
-        .collect::<Vec<_>>()
-        .join(", ");
-    println!("[{}]", result);
+    for cluster in discover_clusters() {
+        harness.pin(&cluster).expect("could not pin to cpu");
+
+        let mut measurements = Vec::new();
+
+        for &pages in &page_policies {
+            harness.set_page_policy(pages);
+
+            for &workload in &workloads {
+                let mut results = vec![Vec::new(); modes.len()];
+
+                for (slot, i) in plan.slots() {
+                    let (name, mode) = modes[i];
+                    unsafe {
+                        set_mte_mode(mode);
+                    }
+                    harness.set_slot(Some(slot));
+                    let name = format!("{}/{}", workload.name(), name);
+                    let measurement = harness.measure_prepared(
+                        &name,
+                        SIZE,
+                        plan.iters_per_round(ITERS),
+                        |mem| unsafe { workload.prepare(mem) },
+                        |mem, tag| unsafe { workload.run(black_box(mem), black_box(tag)) },
+                    );
+                    println!("{}", measurement);
+                    results[i].push(measurement.mean());
+                    measurements.push(measurement);
+                }
+
+                // Mean over all rounds, in the order none, sync, async
+                let result = results.iter()
+                    .map(|rounds| rounds.iter().sum::<Duration>() / rounds.len() as u32)
+                    .map(|result| result.as_millis())
+                    .map(|m| m.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                println!("{} {} with {} pages: [{}]", cluster, workload.name(), pages.name(), result);
+            }
+        }
+
+        println!("{}", throughput_table(&measurements));
+    }
 }
@@ -1,10 +1,19 @@
-use mte_measurement::{memset, set_mte_mode, MTEMode};
+use clap::Parser;
+use mte_measurement::load::Workload;
+use mte_measurement::{set_mte_mode, MTEMode};
 use std::hint::black_box;
 
 // 64 KiB -- should fit into L1 cache of Cortex X3 (128 KiB L1 cache)
 const SIZE: usize = 64 * 1024;
 
-fn measure_custom(iters: u64, mode: MTEMode, f: impl Fn(&mut [u8]) -> ()) {
+#[derive(Parser)]
+struct Args {
+    /// Memory accesses to repeat on the tagged buffer
+    #[arg(value_enum, default_value_t = Workload::Memset)]
+    workload: Workload,
+}
+
+fn measure_custom(iters: u64, mode: MTEMode, workload: Workload) {
     unsafe { set_mte_mode(mode) };
 
     let mem = unsafe {
@@ -17,19 +26,20 @@ fn measure_custom(iters: u64, mode: MTEMode, f: impl Fn(&mut [u8]) -> ()) {
             0,
         )
     };
+    assert_ne!(mem, libc::MAP_FAILED);
 
-    for _ in 0..iters {
-        assert_ne!(mem, libc::MAP_FAILED);
-        let mut mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
+    let mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
+    let tag = unsafe { workload.prepare(mem_slice) };
 
-        f(&mut mem_slice);
+    for _ in 0..iters {
+        unsafe { workload.run(black_box(&mut *mem_slice), black_box(tag)) };
     }
 
     unsafe { libc::munmap(mem, SIZE) };
 }
 
 fn main() {
-    measure_custom(black_box(500000), MTEMode::None, |mem| unsafe {
-        memset(black_box(mem))
-    });
+    let args = Args::parse();
+
+    measure_custom(black_box(500000), MTEMode::None, args.workload);
 }
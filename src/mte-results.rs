@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use mte_measurement::plan::{corrected_mean_ns, position_effects};
+use mte_measurement::plot::{unique, write_charts};
+use mte_measurement::report::markdown;
+use mte_measurement::results::{compare, merge_rounds, Record, ResultStore};
+
+#[derive(Parser)]
+struct Args {
+    /// Directory the binaries were given with `--results-dir`
+    #[arg(long, global = true, default_value = "results")]
+    dir: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all runs in the results directory
+    Runs,
+    /// Compare two runs, exiting with an error if a measurement regressed
+    Compare {
+        /// The run to compare against
+        base: String,
+        /// The run to check for regressions
+        new: String,
+        /// Relative slowdown that counts as a regression
+        #[arg(long, default_value_t = 0.05)]
+        threshold: f64,
+        /// Significance level of Welch's t-test
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+    },
+    /// Render the results of a run as SVG charts
+    Plot {
+        run: String,
+        /// Directory to write the SVG files to
+        #[arg(long, default_value = "plots")]
+        out: PathBuf,
+    },
+    /// Summarise a run as a Markdown document
+    Report {
+        run: String,
+        /// File to write the report to instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Estimate how much the position within a round of interleaved variants affects their
+    /// time, and correct the means for it
+    Positions { run: String },
+}
+
+/// Prints the effect of each position and the mean of every interleaved measurement before and
+/// after correcting for it
+fn positions(records: &[Record]) {
+    let effects = position_effects(records);
+    if effects.is_empty() {
+        println!("the run has no interleaved measurements");
+        return;
+    }
+
+    println!("{:>8} {:>10}", "position", "effect");
+    for (position, effect) in effects.iter().enumerate() {
+        println!("{:>8} {:>+9.2}%", position, (effect - 1.0) * 100.0);
+    }
+
+    let keys = unique(records.iter().filter(|r| r.slot.is_some()).map(Record::key));
+
+    println!();
+    for key in keys {
+        let rounds = records
+            .iter()
+            .filter(|r| r.slot.is_some() && r.key() == key)
+            .collect::<Vec<_>>();
+        let mean = rounds.iter().map(|r| r.mean_ns()).sum::<f64>() / rounds.len() as f64;
+        let corrected = corrected_mean_ns(&rounds, &effects);
+
+        println!(
+            "{} {} {} [{}, {} pages, {} bytes]: {:.0} ns, {:.0} ns corrected ({:+.2}%)",
+            key.core,
+            key.binary,
+            key.variant,
+            key.alloc,
+            key.pages,
+            key.size,
+            mean,
+            corrected,
+            (corrected / mean - 1.0) * 100.0
+        );
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let store = ResultStore::new(args.dir);
+
+    match args.command {
+        Command::Runs => {
+            for run in store.runs().expect("could not read results") {
+                println!("{}", run);
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Compare {
+            base,
+            new,
+            threshold,
+            alpha,
+        } => {
+            let base = merge_rounds(store.load_run(&base).expect("could not load base run"));
+            let new = merge_rounds(store.load_run(&new).expect("could not load new run"));
+
+            let result = match compare(&base, &new) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let mut regressions = 0;
+
+            for c in &result.comparisons {
+                let verdict = if c.is_regression(threshold, alpha) {
+                    regressions += 1;
+                    "regressed"
+                } else if c.is_improvement(threshold, alpha) {
+                    "improved"
+                } else if c.p_value < alpha {
+                    "changed within threshold"
+                } else {
+                    "no change"
+                };
+
+                println!(
+                    "{}: {:.0} ns -> {:.0} ns ({:+.2}%, p = {:.3}) {}",
+                    c.key,
+                    c.base_ns,
+                    c.new_ns,
+                    c.change * 100.0,
+                    c.p_value,
+                    verdict
+                );
+            }
+
+            for key in &result.missing {
+                println!("{}: missing", key);
+            }
+
+            println!(
+                "{} measurements compared, {} regressed by more than {:.1}%, {} missing",
+                result.comparisons.len(),
+                regressions,
+                threshold * 100.0,
+                result.missing.len()
+            );
+
+            if regressions > 0 {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Command::Plot { run, out } => {
+            let records = merge_rounds(store.load_run(&run).expect("could not load run"));
+            for path in write_charts(&records, &out).expect("could not write charts") {
+                println!("{}", path.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Report { run, out } => {
+            let records = merge_rounds(store.load_run(&run).expect("could not load run"));
+            let report = markdown(&run, &records);
+            match out {
+                Some(path) => fs::write(path, report).expect("could not write report"),
+                None => print!("{}", report),
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Positions { run } => {
+            positions(&store.load_run(&run).expect("could not load run"));
+            ExitCode::SUCCESS
+        }
+    }
+}
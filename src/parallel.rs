@@ -0,0 +1,182 @@
+use std::hint::black_box;
+use std::sync::Barrier;
+
+use clap::Parser;
+use mte_measurement::clock::Clock;
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu, Cluster};
+use mte_measurement::harness::{Harness, HarnessConfig, FILL_PATTERN};
+use mte_measurement::region::{AllocPolicy, Region};
+use mte_measurement::variant::{supported, Variant};
+use mte_measurement::{set_mte_mode, stgp_fill, MTEMode};
+use rand::random;
+
+#[derive(Parser)]
+struct Args {
+    /// Total size of the region shared by all threads in MiB
+    #[arg(long, default_value_t = 128)]
+    size_mib: usize,
+    /// Timed iterations per variant and thread count
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    iters: u32,
+    #[command(flatten)]
+    harness: HarnessConfig,
+}
+
+/// A set of CPUs the threads are pinned to, in the order threads are added
+struct Placement {
+    name: String,
+    /// Identifies the placement in stored results, e.g. `Cortex-X3-cpu8`
+    label: String,
+    cpus: Vec<usize>,
+}
+
+/// One placement per cluster, plus one spreading the threads over all clusters in turn
+fn placements(clusters: &[Cluster]) -> Vec<Placement> {
+    let mut placements = clusters
+        .iter()
+        .map(|cluster| Placement {
+            name: cluster.to_string(),
+            label: cluster.label(),
+            cpus: cluster.cpus.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    if clusters.len() > 1 {
+        let longest = clusters.iter().map(|c| c.cpus.len()).max().unwrap_or(0);
+        let cpus = (0..longest)
+            .flat_map(|i| clusters.iter().filter_map(move |c| c.cpus.get(i).copied()))
+            .collect();
+        placements.push(Placement {
+            name: "all clusters".to_string(),
+            label: "all-clusters".to_string(),
+            cpus,
+        });
+    }
+
+    placements
+}
+
+/// Splits `mem` into one chunk per CPU and times `iters` runs of `variant` on all chunks in
+/// parallel, returning the ticks of `clock` per run.
+///
+/// Every thread is pinned to its CPU and enables MTE for itself, as `prctl` only affects the
+/// calling thread. Each sample spans from releasing all threads until the last one finishes.
+/// With `validate`, every thread checks its chunk after the last iteration, so a failure cannot
+/// leave the other threads waiting for it.
+fn measure_parallel(
+    clock: &dyn Clock,
+    mem: &mut [u8],
+    cpus: &[usize],
+    iters: u32,
+    variant: &Variant,
+    validate: bool,
+) -> Vec<u64> {
+    let chunk = mem.len() / cpus.len() / 4096 * 4096;
+    let start = Barrier::new(cpus.len() + 1);
+    let done = Barrier::new(cpus.len() + 1);
+
+    std::thread::scope(|s| {
+        for (&cpu, chunk) in cpus.iter().zip(mem.chunks_exact_mut(chunk)) {
+            let (start, done) = (&start, &done);
+            s.spawn(move || {
+                pin_to_cpu(cpu).expect("could not pin to cpu");
+                unsafe {
+                    set_mte_mode(MTEMode::Sync);
+                }
+
+                let mut tag = 0;
+                for _ in 0..iters {
+                    tag = random();
+                    start.wait();
+                    unsafe { (variant.run)(black_box(&mut *chunk), black_box(tag)) };
+                    done.wait();
+                }
+
+                if validate {
+                    if let Err(e) = unsafe { variant.validate(chunk, tag) } {
+                        panic!("validation failed on cpu {}: {}", cpu, e);
+                    }
+                }
+            });
+        }
+
+        (0..iters)
+            .map(|_| {
+                start.wait();
+                let begin = clock.now();
+                done.wait();
+                clock.now() - begin
+            })
+            .collect()
+    })
+}
+
+fn main() {
+    let args = Args::parse();
+    let size = args.size_mib * 1024 * 1024;
+
+    let variants = supported().collect::<Vec<_>>();
+
+    let placements = placements(&discover_clusters());
+    let threads = placements.iter().map(|p| p.cpus.len()).max().unwrap_or(1);
+    if size / threads < 4096 {
+        eprintln!(
+            "{} MiB cannot be split into a 4 KiB chunk for each of {} threads",
+            args.size_mib, threads
+        );
+        std::process::exit(1);
+    }
+
+    // All threads share one pre-faulted region, whatever `--alloc` says
+    let mut config = args.harness;
+    config.alloc = AllocPolicy::Reused;
+    let mut harness = Harness::new(config);
+    let validate = harness.validates();
+
+    for pages in harness.page_policies() {
+        harness.set_page_policy(pages);
+        let mut region =
+            Region::prepare(size, AllocPolicy::Reused, pages).expect("could not map memory");
+
+        for placement in &placements {
+            let counts = (1..=placement.cpus.len()).collect::<Vec<_>>();
+            let mut table = format!("{:12}", "variant");
+            for n in &counts {
+                table += &format!(" {:>12}", format!("{} threads", n));
+            }
+
+            for variant in &variants {
+                table += &format!("\n{:12}", variant.name);
+
+                for &n in &counts {
+                    let cpus = &placement.cpus[..n];
+                    // Untagged, which `memset` needs to write without faulting, and filled with a
+                    // pattern, so that the zero check proves this variant wrote the zeros
+                    unsafe { stgp_fill(region.as_mut_slice(), 0, FILL_PATTERN) };
+
+                    let bytes = size / n / 4096 * 4096 * n;
+                    let core = format!("{}-{}threads", placement.label, n);
+                    let measurement =
+                        harness.measure_external(variant.name, &core, bytes, |clock| {
+                            measure_parallel(
+                                clock,
+                                region.as_mut_slice(),
+                                cpus,
+                                args.iters,
+                                variant,
+                                validate,
+                            )
+                        });
+
+                    println!("{} threads on cpus {:?}: {}", n, cpus, measurement);
+                    table += &format!(
+                        " {:>12}",
+                        format!("{:.2} GB/s", measurement.throughput() / 1e9)
+                    );
+                }
+            }
+
+            println!("{} with {} pages:\n{}", placement.name, pages.name(), table);
+        }
+    }
+}
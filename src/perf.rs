@@ -0,0 +1,415 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::str::FromStr;
+
+use clap::Args;
+
+use crate::cpu::parse_cpu_list;
+
+const SYSFS_PMUS: &str = "/sys/bus/event_source/devices";
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_TYPE_HW_CACHE: u32 = 3;
+const PERF_TYPE_RAW: u32 = 4;
+
+const PERF_COUNT_HW_CPU_CYCLES: u64 = 0;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_COUNT_HW_STALLED_CYCLES_BACKEND: u64 = 8;
+
+const PERF_COUNT_HW_CACHE_L1D: u64 = 0;
+const PERF_COUNT_HW_CACHE_LL: u64 = 2;
+const PERF_COUNT_HW_CACHE_DTLB: u64 = 3;
+const PERF_COUNT_HW_CACHE_OP_READ: u64 = 0;
+const PERF_COUNT_HW_CACHE_RESULT_MISS: u64 = 1;
+
+const PERF_COUNT_SW_TASK_CLOCK: u64 = 1;
+const PERF_COUNT_SW_PAGE_FAULTS: u64 = 2;
+
+/// Armv8 PMU common event `L2D_CACHE_REFILL`
+const ARMV8_L2D_CACHE_REFILL: u64 = 0x17;
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
+
+const ATTR_FLAG_DISABLED: u64 = 1 << 0;
+const ATTR_FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+/// Hardware and cache events can be routed to a specific PMU by placing its type in the upper
+/// config bits, which is needed on heterogeneous systems with one PMU per cluster.
+const PERF_PMU_TYPE_SHIFT: u32 = 32;
+
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: libc::c_ulong = 0x2401;
+const PERF_IOC_FLAG_GROUP: libc::c_ulong = 1;
+
+/// `struct perf_event_attr` up to `PERF_ATTR_SIZE_VER5`
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+/// An event that can be counted with `perf_event_open`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Counter {
+    Cycles,
+    Instructions,
+    L1dMisses,
+    L2Misses,
+    DtlbMisses,
+    StallCycles,
+    TaskClock,
+    PageFaults,
+}
+
+impl Counter {
+    const ALL: [Counter; 8] = [
+        Counter::Cycles,
+        Counter::Instructions,
+        Counter::L1dMisses,
+        Counter::L2Misses,
+        Counter::DtlbMisses,
+        Counter::StallCycles,
+        Counter::TaskClock,
+        Counter::PageFaults,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::Cycles => "cycles",
+            Counter::Instructions => "instructions",
+            Counter::L1dMisses => "l1d-misses",
+            Counter::L2Misses => "l2-misses",
+            Counter::DtlbMisses => "dtlb-misses",
+            Counter::StallCycles => "stall-cycles",
+            Counter::TaskClock => "task-clock",
+            Counter::PageFaults => "page-faults",
+        }
+    }
+
+    /// Event type and config, routed to the PMU with type `pmu` if given
+    fn event(&self, pmu: Option<u32>) -> (u32, u64) {
+        let cache = |id: u64| {
+            id | (PERF_COUNT_HW_CACHE_OP_READ << 8) | (PERF_COUNT_HW_CACHE_RESULT_MISS << 16)
+        };
+        let extended = |config: u64| match pmu {
+            Some(pmu) => config | ((pmu as u64) << PERF_PMU_TYPE_SHIFT),
+            None => config,
+        };
+
+        match self {
+            Counter::Cycles => (PERF_TYPE_HARDWARE, extended(PERF_COUNT_HW_CPU_CYCLES)),
+            Counter::Instructions => (PERF_TYPE_HARDWARE, extended(PERF_COUNT_HW_INSTRUCTIONS)),
+            Counter::StallCycles => (
+                PERF_TYPE_HARDWARE,
+                extended(PERF_COUNT_HW_STALLED_CYCLES_BACKEND),
+            ),
+            Counter::L1dMisses => (PERF_TYPE_HW_CACHE, extended(cache(PERF_COUNT_HW_CACHE_L1D))),
+            Counter::DtlbMisses => (PERF_TYPE_HW_CACHE, extended(cache(PERF_COUNT_HW_CACHE_DTLB))),
+            Counter::L2Misses if cfg!(target_arch = "aarch64") => {
+                (pmu.unwrap_or(PERF_TYPE_RAW), ARMV8_L2D_CACHE_REFILL)
+            }
+            Counter::L2Misses => (PERF_TYPE_HW_CACHE, extended(cache(PERF_COUNT_HW_CACHE_LL))),
+            Counter::TaskClock => (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK),
+            Counter::PageFaults => (PERF_TYPE_SOFTWARE, PERF_COUNT_SW_PAGE_FAULTS),
+        }
+    }
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Counter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Counter::ALL
+            .into_iter()
+            .find(|c| c.name() == s)
+            .ok_or_else(|| {
+                let names = Counter::ALL.map(|c| c.name()).join(", ");
+                format!("unknown counter `{}`, expected one of {}", s, names)
+            })
+    }
+}
+
+/// Counters that are scheduled onto the PMU together
+#[derive(Clone, Debug)]
+pub struct CounterGroup(pub Vec<Counter>);
+
+impl FromStr for CounterGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .map(Counter::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map(CounterGroup)
+    }
+}
+
+impl fmt::Display for CounterGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self.0.iter().map(|c| c.name()).collect::<Vec<_>>();
+        f.write_str(&names.join(","))
+    }
+}
+
+/// Which performance counters are collected during a measurement
+#[derive(Args, Clone, Debug, Default)]
+pub struct PerfConfig {
+    /// Comma separated group of counters that are scheduled together, e.g. `cycles,instructions`
+    /// (can be repeated)
+    #[arg(long = "counters")]
+    pub groups: Vec<CounterGroup>,
+    /// Do not collect performance counters
+    #[arg(long = "no-counters")]
+    pub disabled: bool,
+}
+
+impl PerfConfig {
+    /// The configured groups, or the default groups if none were given
+    pub fn groups(&self) -> Vec<CounterGroup> {
+        if self.disabled {
+            return Vec::new();
+        }
+        if !self.groups.is_empty() {
+            return self.groups.clone();
+        }
+
+        vec![
+            CounterGroup(vec![Counter::Cycles, Counter::Instructions, Counter::StallCycles]),
+            CounterGroup(vec![Counter::L1dMisses, Counter::L2Misses, Counter::DtlbMisses]),
+        ]
+    }
+}
+
+/// Value of a counter, scaled up if the kernel had to multiplex its group
+#[derive(Clone, Debug)]
+pub struct CounterValue {
+    pub counter: Counter,
+    pub value: u64,
+    /// Fraction of the time the group was actually scheduled on the PMU
+    pub running: f64,
+}
+
+struct Group {
+    counters: Vec<Counter>,
+    fds: Vec<OwnedFd>,
+}
+
+impl Group {
+    fn open(counters: &[Counter], pmu: Option<u32>, exclude_kernel: bool) -> io::Result<Self> {
+        let mut fds: Vec<OwnedFd> = Vec::with_capacity(counters.len());
+
+        for counter in counters {
+            let (type_, config) = counter.event(pmu);
+            let mut attr = PerfEventAttr {
+                type_,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config,
+                read_format: PERF_FORMAT_GROUP
+                    | PERF_FORMAT_TOTAL_TIME_ENABLED
+                    | PERF_FORMAT_TOTAL_TIME_RUNNING,
+                flags: ATTR_FLAG_EXCLUDE_HV,
+                ..Default::default()
+            };
+            if fds.is_empty() {
+                attr.flags |= ATTR_FLAG_DISABLED;
+            }
+            if exclude_kernel {
+                attr.flags |= ATTR_FLAG_EXCLUDE_KERNEL;
+            }
+
+            let leader = fds.first().map_or(-1, |fd| fd.as_raw_fd());
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_perf_event_open,
+                    &attr as *const PerfEventAttr,
+                    0,
+                    -1,
+                    leader,
+                    0,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            fds.push(unsafe { OwnedFd::from_raw_fd(fd as i32) });
+        }
+
+        Ok(Group {
+            counters: counters.to_vec(),
+            fds,
+        })
+    }
+
+    fn ioctl(&self, request: libc::c_ulong) {
+        if let Some(leader) = self.fds.first() {
+            unsafe { libc::ioctl(leader.as_raw_fd(), request as _, PERF_IOC_FLAG_GROUP) };
+        }
+    }
+
+    fn read(&self) -> Vec<CounterValue> {
+        let Some(leader) = self.fds.first() else {
+            return Vec::new();
+        };
+
+        // nr, time_enabled, time_running, values
+        let mut buf = vec![0u64; 3 + self.counters.len()];
+        let size = std::mem::size_of_val(buf.as_slice());
+        let read = unsafe { libc::read(leader.as_raw_fd(), buf.as_mut_ptr().cast(), size) };
+        if read != size as isize {
+            return Vec::new();
+        }
+
+        let (enabled, running) = (buf[1], buf[2]);
+        let fraction = if enabled == 0 {
+            0.0
+        } else {
+            running as f64 / enabled as f64
+        };
+
+        self.counters
+            .iter()
+            .zip(&buf[3..])
+            .map(|(&counter, &raw)| CounterValue {
+                counter,
+                value: if fraction > 0.0 {
+                    (raw as f64 / fraction) as u64
+                } else {
+                    0
+                },
+                running: fraction,
+            })
+            .collect()
+    }
+}
+
+/// Returns the type of the core PMU responsible for `cpu`, if there is one per cluster
+fn core_pmu(cpu: usize) -> Option<u32> {
+    fs::read_dir(SYSFS_PMUS)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|pmu| {
+            fs::read_to_string(pmu.join("cpus"))
+                .map(|cpus| parse_cpu_list(&cpus).contains(&cpu))
+                .unwrap_or(false)
+                && !pmu.join("cpumask").exists()
+        })
+        .and_then(|pmu| fs::read_to_string(pmu.join("type")).ok())
+        .and_then(|t| t.trim().parse().ok())
+}
+
+/// Performance counters of the calling thread.
+///
+/// Groups that cannot be opened are retried in user-only mode and finally replaced by software
+/// counters, so that measurements still work where access to the hardware PMU is denied.
+pub struct Counters {
+    groups: Vec<Group>,
+}
+
+impl Counters {
+    /// Opens `groups` for the calling thread, which is expected to be pinned to `cpu`
+    pub fn open(groups: &[CounterGroup], cpu: Option<usize>) -> Self {
+        let pmu = cpu.and_then(core_pmu);
+        let mut opened = Vec::new();
+        let mut fallback = false;
+
+        for CounterGroup(counters) in groups {
+            let mut attempts = vec![(None, false), (None, true)];
+            if pmu.is_some() {
+                attempts.splice(0..0, [(pmu, false), (pmu, true)]);
+            }
+            let mut error = None;
+
+            let group = attempts
+                .into_iter()
+                .find_map(|(pmu, exclude_kernel)| {
+                    Group::open(counters, pmu, exclude_kernel)
+                        .map_err(|e| error = Some(e))
+                        .ok()
+                });
+
+            match group {
+                Some(group) => opened.push(group),
+                None => {
+                    eprintln!(
+                        "could not open counters {}: {}",
+                        CounterGroup(counters.clone()),
+                        error.map_or_else(|| "unknown error".to_string(), |e| e.to_string())
+                    );
+                    fallback = true;
+                }
+            }
+        }
+
+        if fallback {
+            let software = [Counter::TaskClock, Counter::PageFaults];
+            let have_software = opened
+                .iter()
+                .any(|g| g.counters.iter().any(|c| software.contains(c)));
+            if !have_software {
+                eprintln!("falling back to software counters");
+                if let Ok(group) = Group::open(&software, None, false)
+                    .or_else(|_| Group::open(&software, None, true))
+                {
+                    opened.push(group);
+                }
+            }
+        }
+
+        Counters { groups: opened }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Starts counting, continuing from the previous values
+    pub fn enable(&self) {
+        for group in &self.groups {
+            group.ioctl(PERF_EVENT_IOC_ENABLE);
+        }
+    }
+
+    /// Stops counting
+    pub fn disable(&self) {
+        for group in &self.groups {
+            group.ioctl(PERF_EVENT_IOC_DISABLE);
+        }
+    }
+
+    /// Reads the accumulated value of every counter
+    pub fn read(&self) -> Vec<CounterValue> {
+        self.groups.iter().flat_map(Group::read).collect()
+    }
+}
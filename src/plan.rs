@@ -0,0 +1,243 @@
+use clap::Args;
+use rand::seq::SliceRandom;
+use rand::{random, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::plot::unique;
+use crate::results::Record;
+
+/// How the variants of a binary are ordered
+#[derive(Args, Clone, Debug, Default)]
+pub struct PlanConfig {
+    /// Split the iterations of every variant into this many rounds, each running all variants
+    /// once in a random order
+    #[arg(long, default_value_t = 1)]
+    pub rounds: usize,
+    /// Seed of the random order, to repeat the order of an earlier run [default: random]
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+/// Where a measurement was taken within a plan
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Slot {
+    /// Seed of the plan
+    pub seed: u64,
+    pub round: usize,
+    /// Index within the round, 0 for the variant that ran first
+    pub position: usize,
+}
+
+/// Order in which variants are measured: rounds that each run every variant once, shuffled with
+/// a seeded RNG so that thermal drift does not always penalise the same variants
+#[derive(Clone, Debug)]
+pub struct Plan {
+    pub seed: u64,
+    /// Indices of the variants in the order they run, per round
+    pub rounds: Vec<Vec<usize>>,
+}
+
+impl Plan {
+    pub fn new(config: &PlanConfig, variants: usize) -> Self {
+        let seed = config.seed.unwrap_or_else(random);
+        // Unlike `StdRng`, ChaCha8 is guaranteed to produce the same order for a seed across
+        // versions of `rand`
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let rounds = (0..config.rounds.max(1))
+            .map(|_| {
+                let mut order = (0..variants).collect::<Vec<_>>();
+                order.shuffle(&mut rng);
+                order
+            })
+            .collect();
+
+        Plan { seed, rounds }
+    }
+
+    /// Iterations per variant and round, so that all rounds together run about `iters`
+    pub fn iters_per_round(&self, iters: u64) -> u64 {
+        iters.div_ceil(self.rounds.len() as u64).max(1)
+    }
+
+    /// Every variant index in execution order, with its slot
+    pub fn slots(&self) -> impl Iterator<Item = (Slot, usize)> + '_ {
+        self.rounds
+            .iter()
+            .enumerate()
+            .flat_map(move |(round, order)| {
+                order.iter().enumerate().map(move |(position, &variant)| {
+                    let slot = Slot {
+                        seed: self.seed,
+                        round,
+                        position,
+                    };
+                    (slot, variant)
+                })
+            })
+    }
+
+    /// The order of every round, naming the variants with `names`
+    pub fn describe(&self, names: &[&str]) -> String {
+        let rounds = self
+            .rounds
+            .iter()
+            .map(|order| {
+                let order = order.iter().map(|&v| names[v]).collect::<Vec<_>>();
+                format!("[{}]", order.join(" "))
+            })
+            .collect::<Vec<_>>();
+        format!("seed {}: {}", self.seed, rounds.join(" "))
+    }
+}
+
+/// Slot and mean time of each interleaved record, relative to the mean of all rounds of the same
+/// measurement
+fn relative_times(records: &[Record]) -> Vec<(Slot, f64)> {
+    let keys = unique(records.iter().filter(|r| r.slot.is_some()).map(Record::key));
+
+    keys.iter()
+        .flat_map(|key| {
+            let rounds = records
+                .iter()
+                .filter(|r| r.key() == *key)
+                .filter_map(|r| Some((r, r.slot?)))
+                .collect::<Vec<_>>();
+            let mean = rounds.iter().map(|(r, _)| r.mean_ns()).sum::<f64>() / rounds.len() as f64;
+            rounds
+                .into_iter()
+                .map(move |(r, slot)| (slot, r.mean_ns() / mean))
+        })
+        .collect()
+}
+
+/// Average slowdown of the variant at each position of a round, as a factor relative to the
+/// variant's own mean over all rounds, estimated from all interleaved records.
+///
+/// As the order is random, every variant is equally likely at every position, so the factor
+/// measures the effect of the position itself, e.g. of the device heating up during a round.
+/// With a single round every factor is 1.
+pub fn position_effects(records: &[Record]) -> Vec<f64> {
+    let times = relative_times(records);
+    let positions = times.iter().map(|(s, _)| s.position + 1).max().unwrap_or(0);
+
+    (0..positions)
+        .map(|position| {
+            let factors = times
+                .iter()
+                .filter(|(s, _)| s.position == position)
+                .map(|&(_, factor)| factor)
+                .collect::<Vec<_>>();
+            factors.iter().sum::<f64>() / factors.len().max(1) as f64
+        })
+        .collect()
+}
+
+/// Mean time of the records of one measurement in nanoseconds, with the samples of every round
+/// divided by the effect of the position it ran at
+pub fn corrected_mean_ns(records: &[&Record], effects: &[f64]) -> f64 {
+    let corrected = records
+        .iter()
+        .map(|r| {
+            let effect = r
+                .slot
+                .and_then(|s| effects.get(s.position))
+                .copied()
+                .unwrap_or(1.0);
+            r.mean_ns() / effect
+        })
+        .collect::<Vec<_>>();
+    corrected.iter().sum::<f64>() / corrected.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::tests::record;
+
+    /// A record of `variant` with mean `ns` that ran at `position` of `round`
+    fn at(round: usize, position: usize, variant: &str, ns: f64) -> Record {
+        let mut record = record("run", variant, &[ns, ns]);
+        record.slot = Some(Slot {
+            seed: 42,
+            round,
+            position,
+        });
+        record
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn seed_fixes_the_order() {
+        let config = PlanConfig {
+            rounds: 2,
+            seed: Some(42),
+        };
+        let plan = Plan::new(&config, 5);
+        // Changes here would stop old seeds from repeating the order of earlier runs
+        assert_eq!(plan.rounds, [[2, 1, 0, 4, 3], [0, 4, 2, 3, 1]]);
+        assert_eq!(
+            plan.describe(&["a", "b", "c", "d", "e"]),
+            "seed 42: [c b a e d] [a e c d b]"
+        );
+    }
+
+    #[test]
+    fn position_effects_compare_each_variant_to_its_own_mean() {
+        // Both variants are 5% slower when they run second
+        let records = [
+            at(0, 0, "stg", 100.0),
+            at(0, 1, "stzg", 220.0),
+            at(1, 0, "stzg", 200.0),
+            at(1, 1, "stg", 110.0),
+            // Not interleaved, so left out
+            record("run", "memset", &[1000.0]),
+        ];
+
+        let effects = position_effects(&records);
+        assert_eq!(effects.len(), 2);
+        assert_close(effects[0], 200.0 / 210.0);
+        assert_close(effects[1], 220.0 / 210.0);
+
+        for (variant, expected) in [("stg", 105.0), ("stzg", 210.0)] {
+            let rounds = records
+                .iter()
+                .filter(|r| r.variant == variant)
+                .collect::<Vec<_>>();
+            assert_close(corrected_mean_ns(&rounds, &effects), expected);
+        }
+    }
+
+    #[test]
+    fn single_round_has_no_position_effect() {
+        let records = [
+            at(0, 0, "stzg", 200.0),
+            at(0, 1, "stg", 100.0),
+            at(0, 2, "memset", 50.0),
+        ];
+
+        let effects = position_effects(&records);
+        assert_eq!(effects, [1.0, 1.0, 1.0]);
+
+        let rounds = records.iter().take(1).collect::<Vec<_>>();
+        assert_eq!(corrected_mean_ns(&rounds, &effects), 200.0);
+    }
+
+    #[test]
+    fn no_interleaved_records_have_no_positions() {
+        let records = [record("run", "stg", &[100.0])];
+        assert!(position_effects(&records).is_empty());
+
+        let rounds = records.iter().collect::<Vec<_>>();
+        assert_eq!(corrected_mean_ns(&rounds, &[]), 100.0);
+    }
+}
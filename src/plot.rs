@@ -0,0 +1,433 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::results::Record;
+use crate::stats;
+
+const WIDTH: f64 = 800.0;
+const HEIGHT: f64 = 480.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 170.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 90.0;
+
+const PALETTE: [&str; 8] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#9c755f",
+];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns evenly spaced, round tick values from 0 to at least `max`
+fn ticks(max: f64) -> Vec<f64> {
+    if max <= 0.0 || !max.is_finite() {
+        return vec![0.0, 1.0];
+    }
+
+    let rough = max / 5.0;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= rough)
+        .unwrap_or(10.0 * magnitude);
+
+    let count = (max / step).ceil() as usize;
+    (0..=count).map(|i| i as f64 * step).collect()
+}
+
+fn format_tick(value: f64) -> String {
+    if value == value.trunc() {
+        format!("{:.0}", value)
+    } else {
+        format!("{}", (value * 1000.0).round() / 1000.0)
+    }
+}
+
+pub(crate) fn format_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1 << 30 && b % (1 << 30) == 0 => format!("{} GiB", b >> 30),
+        b if b >= 1 << 20 && b % (1 << 20) == 0 => format!("{} MiB", b >> 20),
+        b if b >= 1 << 10 && b % (1 << 10) == 0 => format!("{} KiB", b >> 10),
+        b => format!("{} B", b),
+    }
+}
+
+/// An SVG document with a plot area, a y axis and a legend
+struct Chart {
+    svg: String,
+    y_max: f64,
+}
+
+impl Chart {
+    fn new(title: &str, y_label: &str, y_ticks: &[f64]) -> Self {
+        let mut svg = String::new();
+        let y_max = y_ticks.last().copied().unwrap_or(1.0);
+
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="12">"#,
+            w = WIDTH,
+            h = HEIGHT
+        );
+        let _ = writeln!(
+            svg,
+            r#"<rect width="{}" height="{}" fill="white"/>"#,
+            WIDTH, HEIGHT
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle" font-size="16">{}</text>"#,
+            (MARGIN_LEFT + WIDTH - MARGIN_RIGHT) / 2.0,
+            MARGIN_TOP / 2.0 + 6.0,
+            escape(title)
+        );
+
+        let mut chart = Chart { svg, y_max };
+
+        for &tick in y_ticks {
+            let y = chart.y(tick);
+            let _ = writeln!(
+                chart.svg,
+                r##"<line x1="{}" y1="{y}" x2="{}" y2="{y}" stroke="#ddd"/>"##,
+                MARGIN_LEFT,
+                WIDTH - MARGIN_RIGHT
+            );
+            let _ = writeln!(
+                chart.svg,
+                r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+                MARGIN_LEFT - 6.0,
+                y + 4.0,
+                format_tick(tick)
+            );
+        }
+
+        let _ = writeln!(
+            chart.svg,
+            r#"<text transform="translate({} {}) rotate(-90)" text-anchor="middle">{}</text>"#,
+            18.0,
+            (MARGIN_TOP + HEIGHT - MARGIN_BOTTOM) / 2.0,
+            escape(y_label)
+        );
+        let _ = writeln!(
+            chart.svg,
+            r#"<line x1="{l}" y1="{t}" x2="{l}" y2="{b}" stroke="black"/><line x1="{l}" y1="{b}" x2="{r}" y2="{b}" stroke="black"/>"#,
+            l = MARGIN_LEFT,
+            t = MARGIN_TOP,
+            b = HEIGHT - MARGIN_BOTTOM,
+            r = WIDTH - MARGIN_RIGHT
+        );
+
+        chart
+    }
+
+    fn plot_width(&self) -> f64 {
+        WIDTH - MARGIN_LEFT - MARGIN_RIGHT
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        let height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        HEIGHT - MARGIN_BOTTOM - value / self.y_max * height
+    }
+
+    fn x_label(&mut self, x: f64, label: &str) {
+        let y = HEIGHT - MARGIN_BOTTOM + 14.0;
+        let _ = writeln!(
+            self.svg,
+            r#"<text transform="translate({x} {y}) rotate(-30)" text-anchor="end">{}</text>"#,
+            escape(label)
+        );
+    }
+
+    fn legend(&mut self, entries: &[&str]) {
+        for (i, entry) in entries.iter().enumerate() {
+            let x = WIDTH - MARGIN_RIGHT + 15.0;
+            let y = MARGIN_TOP + i as f64 * 20.0;
+            let _ = writeln!(
+                self.svg,
+                r#"<rect x="{x}" y="{y}" width="12" height="12" fill="{}"/><text x="{}" y="{}">{}</text>"#,
+                PALETTE[i % PALETTE.len()],
+                x + 18.0,
+                y + 10.0,
+                escape(entry)
+            );
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.svg.push_str("</svg>\n");
+        self.svg
+    }
+}
+
+/// The distinct values, in the order they first appear
+pub fn unique<T: PartialEq>(values: impl Iterator<Item = T>) -> Vec<T> {
+    let mut unique = Vec::new();
+    for value in values {
+        if !unique.contains(&value) {
+            unique.push(value);
+        }
+    }
+    unique
+}
+
+/// Grouped bar chart of the mean time of every variant (groups) on every core (bars)
+pub fn bar_chart(title: &str, records: &[Record]) -> String {
+    let variants = unique(records.iter().map(|r| r.variant.as_str()));
+    let cores = unique(records.iter().map(|r| r.core.as_str()));
+
+    let mean_ms = |variant: &str, core: &str| {
+        records
+            .iter()
+            .find(|r| r.variant == variant && r.core == core)
+            .map(|r| r.mean_ns() / 1e6)
+    };
+    let max = records
+        .iter()
+        .map(|r| r.mean_ns() / 1e6)
+        .fold(0.0, f64::max);
+
+    let mut chart = Chart::new(title, "mean time (ms)", &ticks(max));
+    let group_width = chart.plot_width() / variants.len().max(1) as f64;
+    let bar_width = group_width * 0.8 / cores.len().max(1) as f64;
+
+    for (v, variant) in variants.iter().enumerate() {
+        let group_x = MARGIN_LEFT + v as f64 * group_width + group_width * 0.1;
+
+        for (c, core) in cores.iter().enumerate() {
+            let Some(value) = mean_ms(variant, core) else {
+                continue;
+            };
+            let x = group_x + c as f64 * bar_width;
+            let y = chart.y(value);
+            let _ = writeln!(
+                chart.svg,
+                r#"<rect x="{x:.1}" y="{y:.1}" width="{:.1}" height="{:.1}" fill="{}"><title>{} on {}: {:.2} ms</title></rect>"#,
+                bar_width,
+                HEIGHT - MARGIN_BOTTOM - y,
+                PALETTE[c % PALETTE.len()],
+                escape(variant),
+                escape(core),
+                value
+            );
+        }
+
+        chart.x_label(group_x + group_width * 0.4, variant);
+    }
+
+    chart.legend(&cores);
+    chart.finish()
+}
+
+/// Line chart of the throughput of every variant over the region size, with a logarithmic x axis
+pub fn line_chart(title: &str, records: &[Record]) -> String {
+    let variants = unique(records.iter().map(|r| r.variant.as_str()));
+    let mut sizes = records.iter().map(|r| r.size).collect::<Vec<_>>();
+    sizes.sort_unstable();
+    sizes.dedup();
+
+    let throughput = |r: &Record| r.size as f64 / stats::mean(&r.samples_ns);
+    let max = records.iter().map(throughput).fold(0.0, f64::max);
+
+    let mut chart = Chart::new(title, "throughput (GB/s)", &ticks(max));
+    let (min_log, max_log) = (
+        (sizes[0] as f64).log2(),
+        (sizes[sizes.len() - 1] as f64).log2(),
+    );
+    let plot_width = chart.plot_width();
+    let x = |size: usize| {
+        let span = (max_log - min_log).max(1.0);
+        MARGIN_LEFT + 10.0 + ((size as f64).log2() - min_log) / span * (plot_width - 20.0)
+    };
+
+    for &size in &sizes {
+        chart.x_label(x(size), &format_size(size));
+    }
+
+    for (v, variant) in variants.iter().enumerate() {
+        let mut points = records
+            .iter()
+            .filter(|r| r.variant == *variant)
+            .map(|r| (r.size, throughput(r)))
+            .collect::<Vec<_>>();
+        points.sort_by_key(|&(size, _)| size);
+
+        let color = PALETTE[v % PALETTE.len()];
+        let path = points
+            .iter()
+            .map(|&(size, gbps)| format!("{:.1},{:.1}", x(size), chart.y(gbps)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(
+            chart.svg,
+            r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+            path, color
+        );
+        for &(size, gbps) in &points {
+            let _ = writeln!(
+                chart.svg,
+                r#"<circle cx="{:.1}" cy="{:.1}" r="3" fill="{}"><title>{} at {}: {:.2} GB/s</title></circle>"#,
+                x(size),
+                chart.y(gbps),
+                color,
+                escape(variant),
+                format_size(size),
+                gbps
+            );
+        }
+    }
+
+    chart.legend(&variants);
+    chart.finish()
+}
+
+/// Writes a bar chart per binary, allocation and page policy, or a line chart per core if the
+/// measurements were taken at several sizes
+pub fn write_charts(records: &[Record], out: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(out)?;
+
+    let groups = unique(
+        records
+            .iter()
+            .map(|r| (r.binary.as_str(), r.alloc.as_str(), r.pages.as_str())),
+    );
+
+    let mut written = Vec::new();
+    for (binary, alloc, pages) in groups {
+        let group = records
+            .iter()
+            .filter(|r| r.binary == binary && r.alloc == alloc && r.pages == pages)
+            .cloned()
+            .collect::<Vec<_>>();
+        let name = format!("{}-{}-{}", binary, alloc, pages);
+
+        let mut sizes = group.iter().map(|r| r.size).collect::<Vec<_>>();
+        sizes.sort_unstable();
+        sizes.dedup();
+
+        if sizes.len() > 1 {
+            for core in unique(group.iter().map(|r| r.core.as_str())) {
+                let records = group
+                    .iter()
+                    .filter(|r| r.core == core)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let title = format!(
+                    "{} on {} ({} allocation, {} pages)",
+                    binary, core, alloc, pages
+                );
+                let path = out.join(format!("{}-{}-sweep.svg", name, core));
+                fs::write(&path, line_chart(&title, &records))?;
+                written.push(path);
+            }
+        } else {
+            let title = format!("{} ({} allocation, {} pages)", binary, alloc, pages);
+            let path = out.join(format!("{}.svg", name));
+            fs::write(&path, bar_chart(&title, &group))?;
+            written.push(path);
+        }
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::tests::record;
+
+    fn on(core: &str, size: usize, mut record: Record) -> Record {
+        record.core = core.to_string();
+        record.size = size;
+        record
+    }
+
+    #[test]
+    fn ticks_cover_the_maximum() {
+        assert_eq!(ticks(9.0), [0.0, 2.0, 4.0, 6.0, 8.0, 10.0]);
+        assert_eq!(ticks(45.0), [0.0, 10.0, 20.0, 30.0, 40.0, 50.0]);
+        assert_eq!(ticks(0.0), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn bar_chart_has_one_bar_per_record() {
+        let records = [
+            on("X3", 4096, record("a", "stg", &[1e6, 1e6])),
+            on("A510", 4096, record("a", "stg", &[3.5e6, 3.5e6])),
+            on("X3", 4096, record("a", "stzg", &[2e6, 2e6])),
+        ];
+        let svg = bar_chart("title", &records);
+
+        assert_eq!(svg.matches("</title></rect>").count(), 3);
+        assert!(svg.contains("<title>stg on A510: 3.50 ms</title>"));
+        // The y axis ends at the first tick above the slowest bar
+        assert!(svg.contains(r#"text-anchor="end">4</text>"#));
+        assert!(!svg.contains(r#"text-anchor="end">5</text>"#));
+        // One legend entry per core, even though the cores alternate
+        assert_eq!(svg.matches(r#"width="12" height="12""#).count(), 2);
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn line_chart_has_one_point_per_record() {
+        let records = [
+            on("X3", 4096, record("a", "stg", &[1000.0])),
+            on("X3", 1 << 20, record("a", "stg", &[100_000.0])),
+            on("X3", 4096, record("a", "stzg", &[2000.0])),
+            on("X3", 1 << 20, record("a", "stzg", &[200_000.0])),
+        ];
+        let svg = line_chart("title", &records);
+
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        assert_eq!(svg.matches("<circle").count(), 4);
+        assert!(svg.contains("<title>stg at 1 MiB: 10.49 GB/s</title>"));
+        assert!(svg.contains(">4 KiB</text>"));
+    }
+
+    #[test]
+    fn writes_one_sweep_per_core() {
+        // Records of the same core are not adjacent
+        let records = [
+            on("X3", 4096, record("a", "stg", &[1000.0])),
+            on("A510", 4096, record("a", "stg", &[3000.0])),
+            on("X3", 65536, record("a", "stg", &[10_000.0])),
+            on("A510", 65536, record("a", "stg", &[30_000.0])),
+        ];
+        let out = tempfile::tempdir().unwrap();
+
+        let written = write_charts(&records, out.path()).unwrap();
+        let names = written
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "stg-fresh-default-X3-sweep.svg",
+                "stg-fresh-default-A510-sweep.svg"
+            ]
+        );
+        for path in &written {
+            let svg = fs::read_to_string(path).unwrap();
+            assert_eq!(svg.matches("<circle").count(), 2);
+        }
+    }
+
+    #[test]
+    fn writes_a_bar_chart_for_a_single_size() {
+        let records = [
+            on("X3", 4096, record("a", "stg", &[1000.0])),
+            on("A510", 4096, record("a", "stg", &[3000.0])),
+        ];
+        let out = tempfile::tempdir().unwrap();
+
+        let written = write_charts(&records, out.path()).unwrap();
+        assert_eq!(written, [out.path().join("stg-fresh-default.svg")]);
+    }
+}
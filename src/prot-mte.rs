@@ -0,0 +1,163 @@
+use clap::Parser;
+use mte_measurement::clock::{Clock, ClockKind};
+use mte_measurement::cpu::{discover_clusters, pin_to_cpu};
+use mte_measurement::region::{huge_page_size, PagePolicy, Region, PROT_MTE};
+use mte_measurement::stg;
+use mte_measurement::thermal::{Cooldown, CooldownConfig};
+use rand::random;
+use std::time::Duration;
+
+const RW: i32 = libc::PROT_READ | libc::PROT_WRITE;
+
+/// Timed operations, in the order [`measure`] returns them
+const OPERATIONS: [&str; 7] = [
+    "mmap+touch",
+    "mmap+touch PROT_MTE",
+    "munmap",
+    "munmap PROT_MTE",
+    "mprotect +PROT_MTE",
+    "touch after mprotect",
+    "stg",
+];
+
+#[derive(Parser)]
+struct Args {
+    /// Sizes of the mappings in bytes, comma separated
+    #[arg(long, value_delimiter = ',', default_values_t = [64 << 10, 2 << 20, 16 << 20, 128 << 20])]
+    sizes: Vec<usize>,
+    /// Timed samples per operation and size
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    iters: u32,
+    /// Page sizes to measure, comma separated; unsupported ones are skipped
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [PagePolicy::Default])]
+    pages: Vec<PagePolicy>,
+    /// Time source used to time each operation
+    #[arg(long, value_enum, default_value_t = ClockKind::Auto)]
+    clock: ClockKind,
+    #[command(flatten)]
+    cooldown: CooldownConfig,
+}
+
+fn map(size: usize, prot: i32, pages: PagePolicy) -> Region {
+    let flags = if pages == PagePolicy::Hugetlb {
+        libc::MAP_HUGETLB
+    } else {
+        0
+    };
+    let mut region = Region::map_prot(size, prot, flags).expect("could not map memory");
+    if pages == PagePolicy::Thp {
+        region
+            .advise_hugepage()
+            .expect("could not request huge pages");
+    }
+    region
+}
+
+fn timed<T>(clock: &dyn Clock, f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = clock.now();
+    let result = f();
+    (result, clock.to_duration(clock.now() - start))
+}
+
+/// Mean duration of each of the [`OPERATIONS`] on `size` bytes backed by `pages`
+fn measure(clock: &dyn Clock, size: usize, pages: PagePolicy, iters: u32) -> Vec<Duration> {
+    let mut totals = vec![Duration::ZERO; OPERATIONS.len()];
+
+    for _ in 0..iters {
+        for (i, prot) in [RW, RW | PROT_MTE].into_iter().enumerate() {
+            let (region, t) = timed(clock, || {
+                let mut region = map(size, prot, pages);
+                region.touch();
+                region
+            });
+            totals[i] += t;
+
+            let ((), t) = timed(clock, || drop(region));
+            totals[2 + i] += t;
+        }
+
+        let mut region = map(size, RW, pages);
+        region.touch();
+
+        let (result, t) = timed(clock, || region.protect(RW | PROT_MTE));
+        result.expect("could not add PROT_MTE");
+        totals[4] += t;
+
+        let ((), t) = timed(clock, || region.touch());
+        totals[5] += t;
+
+        let ((), t) = timed(clock, || unsafe { stg(region.as_mut_slice(), random()) });
+        totals[6] += t;
+    }
+
+    totals.iter().map(|&total| total / iters).collect()
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let page_policies = args
+        .pages
+        .iter()
+        .copied()
+        .filter(|pages| match pages.check() {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("skipping {} pages: {}", pages.name(), e);
+                false
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let clock = args.clock.create().expect("could not create clock");
+    let cooldown = Cooldown::new(args.cooldown);
+
+    for cluster in discover_clusters() {
+        pin_to_cpu(cluster.representative()).expect("could not pin to cpu");
+
+        for &pages in &page_policies {
+            // hugetlb mappings have to be a multiple of the huge page size
+            let sizes = args
+                .sizes
+                .iter()
+                .copied()
+                .filter(|&size| pages != PagePolicy::Hugetlb || size % huge_page_size() == 0)
+                .collect::<Vec<_>>();
+
+            let mut results = Vec::new();
+            for &size in &sizes {
+                cooldown.wait();
+                let means = measure(clock.as_ref(), size, pages, args.iters);
+                for (operation, mean) in OPERATIONS.iter().zip(&means) {
+                    println!(
+                        "{} with {} pages, {} bytes: {} {:.2?}",
+                        cluster,
+                        pages.name(),
+                        size,
+                        operation,
+                        mean
+                    );
+                }
+                results.push(means);
+            }
+
+            let mut table = format!("{:22}", "operation");
+            for size in &sizes {
+                table += &format!(" {:>12}", format!("{} KiB", size / 1024));
+            }
+            for (i, operation) in OPERATIONS.iter().enumerate() {
+                table += &format!("\n{:22}", operation);
+                for means in &results {
+                    table += &format!(" {:>12}", format!("{:.2?}", means[i]));
+                }
+            }
+            table += &format!("\n{:22}", "PROT_MTE overhead");
+            for means in &results {
+                let overhead = means[1].as_secs_f64() / means[0].as_secs_f64() - 1.0;
+                table += &format!(" {:>12}", format!("{:+.1}%", overhead * 100.0));
+            }
+
+            println!("{} with {} pages:\n{}", cluster, pages.name(), table);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+pub const PROT_MTE: i32 = 0x20;
+
+/// How the memory for each timed iteration is provided
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AllocPolicy {
+    /// A fresh mapping per iteration, so page faults are part of the timing
+    #[default]
+    Fresh,
+    /// A fresh mapping per iteration, pre-faulted with `MAP_POPULATE`
+    Populate,
+    /// A fresh mapping per iteration, touched once per page before the timed section
+    PreTouch,
+    /// One pre-touched mapping reused for all iterations of a variant
+    Reused,
+}
+
+impl AllocPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AllocPolicy::Fresh => "fresh",
+            AllocPolicy::Populate => "populate",
+            AllocPolicy::PreTouch => "pre-touch",
+            AllocPolicy::Reused => "reused",
+        }
+    }
+}
+
+/// Which page size backs the memory
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PagePolicy {
+    /// Base pages
+    #[default]
+    Default,
+    /// Transparent huge pages requested with `madvise(MADV_HUGEPAGE)`
+    Thp,
+    /// Explicit huge pages from the hugetlb pool with `MAP_HUGETLB`
+    Hugetlb,
+}
+
+/// Size of a PMD huge page, read once from sysfs, or 2 MiB (the size with 4 KiB base pages) if
+/// the kernel does not report it
+pub fn huge_page_size() -> usize {
+    static SIZE: OnceLock<usize> = OnceLock::new();
+    *SIZE.get_or_init(|| {
+        fs::read_to_string("/sys/kernel/mm/transparent_hugepage/hpage_pmd_size")
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .unwrap_or(2 * 1024 * 1024)
+    })
+}
+
+impl PagePolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PagePolicy::Default => "default",
+            PagePolicy::Thp => "thp",
+            PagePolicy::Hugetlb => "hugetlb",
+        }
+    }
+
+    /// Checks whether tagged memory can actually be backed by this page size on this kernel
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            PagePolicy::Default => Ok(()),
+            PagePolicy::Hugetlb => {
+                let mut region = Region::map(huge_page_size(), libc::MAP_HUGETLB)
+                    .map_err(|e| format!("could not map a PROT_MTE hugetlb page: {}", e))?;
+                region.touch();
+                Ok(())
+            }
+            PagePolicy::Thp => {
+                let enabled = fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")
+                    .map_err(|e| format!("transparent huge pages are not available: {}", e))?;
+                if enabled.contains("[never]") {
+                    return Err("transparent huge pages are disabled".to_string());
+                }
+
+                let mut region = Region::map(2 * huge_page_size(), 0)
+                    .map_err(|e| format!("could not map memory: {}", e))?;
+                region
+                    .advise_hugepage()
+                    .map_err(|e| format!("madvise(MADV_HUGEPAGE) failed: {}", e))?;
+                region.touch();
+
+                match region.anon_huge_kb() {
+                    Some(kb) if kb > 0 => Ok(()),
+                    _ => Err("PROT_MTE memory is not backed by transparent huge pages".to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// An anonymous private mapping, normally with `PROT_MTE`, unmapped on drop
+pub struct Region {
+    ptr: *mut u8,
+    size: usize,
+}
+
+impl Region {
+    /// Maps `size` bytes of tagged memory, passing `flags` to `mmap` in addition to
+    /// `MAP_PRIVATE | MAP_ANONYMOUS`
+    pub fn map(size: usize, flags: i32) -> io::Result<Self> {
+        Self::map_prot(size, libc::PROT_READ | libc::PROT_WRITE | PROT_MTE, flags)
+    }
+
+    /// Maps `size` bytes with protection `prot`, e.g. without `PROT_MTE` for comparison
+    pub fn map_prot(size: usize, prot: i32, flags: i32) -> io::Result<Self> {
+        let mem = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                prot,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+                -1,
+                0,
+            )
+        };
+
+        if mem == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Region {
+            ptr: mem as *mut u8,
+            size,
+        })
+    }
+
+    /// Maps a region backed by `pages` and prepares it according to `alloc`.
+    ///
+    /// `MAP_POPULATE` would fault in base pages before `madvise` can request huge pages, so
+    /// transparent huge page regions are populated by touching them instead.
+    pub fn prepare(size: usize, alloc: AllocPolicy, pages: PagePolicy) -> io::Result<Self> {
+        let mut flags = 0;
+        if alloc == AllocPolicy::Populate && pages != PagePolicy::Thp {
+            flags |= libc::MAP_POPULATE;
+        }
+        if pages == PagePolicy::Hugetlb {
+            flags |= libc::MAP_HUGETLB;
+        }
+
+        let mut region = Self::map(size, flags)?;
+
+        if pages == PagePolicy::Thp {
+            region.advise_hugepage()?;
+        }
+
+        let touch = match alloc {
+            AllocPolicy::Fresh => false,
+            AllocPolicy::Populate => pages == PagePolicy::Thp,
+            AllocPolicy::PreTouch | AllocPolicy::Reused => true,
+        };
+        if touch {
+            region.touch();
+        }
+
+        Ok(region)
+    }
+
+    pub fn advise_hugepage(&mut self) -> io::Result<()> {
+        if unsafe { libc::madvise(self.ptr.cast(), self.size, libc::MADV_HUGEPAGE) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Changes the protection of the whole region, e.g. to add `PROT_MTE`
+    pub fn protect(&mut self, prot: i32) -> io::Result<()> {
+        if unsafe { libc::mprotect(self.ptr.cast(), self.size, prot) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// `AnonHugePages` of this mapping in `/proc/self/smaps`
+    pub fn anon_huge_kb(&self) -> Option<u64> {
+        let smaps = fs::read_to_string("/proc/self/smaps").ok()?;
+        let start = self.ptr as usize;
+        anon_huge_kb_in(&smaps, start..start + self.size)
+    }
+
+    /// Writes one byte per page to fault in the whole region
+    pub fn touch(&mut self) {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        for offset in (0..self.size).step_by(page_size) {
+            unsafe { std::ptr::write_volatile(self.ptr.add(offset), 0) };
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.size) }
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.size) };
+    }
+}
+
+/// Sums `AnonHugePages` over the VMAs in `smaps` that overlap `range`.
+///
+/// The kernel merges adjacent mappings with the same flags into one VMA, so the region does not
+/// necessarily start a VMA of its own.
+fn anon_huge_kb_in(smaps: &str, range: Range<usize>) -> Option<u64> {
+    let mut overlaps = false;
+    let mut total = None;
+
+    for line in smaps.lines() {
+        let first = line.split_whitespace().next().unwrap_or_default();
+        let vma = first.split_once('-').and_then(|(start, end)| {
+            Some(usize::from_str_radix(start, 16).ok()?..usize::from_str_radix(end, 16).ok()?)
+        });
+
+        match vma {
+            Some(vma) => overlaps = vma.start < range.end && range.start < vma.end,
+            None if overlaps => {
+                if let Some(value) = line.strip_prefix("AnonHugePages:") {
+                    let kb: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+                    *total.get_or_insert(0) += kb;
+                }
+            }
+            None => {}
+        }
+    }
+
+    total
+}
+
+/// Minor and major page faults of the calling thread so far
+pub fn page_faults() -> u64 {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_THREAD, &mut usage) };
+    (usage.ru_minflt + usage.ru_majflt) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMAPS: &str = "\
+7f0000000000-7f0000400000 rw-p 00000000 00:00 0
+Size:               4096 kB
+AnonHugePages:      2048 kB
+VmFlags: rd wr mr mw me ac hg mt
+7f0000400000-7f0000600000 rw-p 00000000 00:00 0
+Size:               2048 kB
+AnonHugePages:         0 kB
+VmFlags: rd wr mr mw me ac
+7f0000600000-7f0000800000 rw-p 00000000 00:00 0
+Size:               2048 kB
+AnonHugePages:      2048 kB
+VmFlags: rd wr mr mw me ac hg mt
+";
+
+    #[test]
+    fn finds_the_vma_containing_the_region() {
+        // Starts in the middle of the first VMA, which another mapping was merged into
+        let kb = anon_huge_kb_in(SMAPS, 0x7f0000200000..0x7f0000400000);
+        assert_eq!(kb, Some(2048));
+
+        assert_eq!(
+            anon_huge_kb_in(SMAPS, 0x7f0000400000..0x7f0000600000),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn sums_all_overlapping_vmas() {
+        let kb = anon_huge_kb_in(SMAPS, 0x7f0000000000..0x7f0000800000);
+        assert_eq!(kb, Some(4096));
+    }
+
+    #[test]
+    fn unmapped_region_has_no_entry() {
+        assert_eq!(anon_huge_kb_in(SMAPS, 0x1000..0x2000), None);
+    }
+}
@@ -0,0 +1,286 @@
+use std::fmt::Write;
+
+use crate::plot::{format_size, unique};
+use crate::results::{format_timestamp, Environment, Record};
+use crate::stats;
+use crate::variant::{Variant, VARIANTS};
+
+/// Variant every other variant is compared against
+const BASELINE: &str = "memset";
+
+fn format_ns(ns: f64) -> String {
+    match ns {
+        ns if ns >= 1e9 => format!("{:.2} s", ns / 1e9),
+        ns if ns >= 1e6 => format!("{:.2} ms", ns / 1e6),
+        ns if ns >= 1e3 => format!("{:.2} µs", ns / 1e3),
+        ns => format!("{:.0} ns", ns),
+    }
+}
+
+fn table_row(cells: &[String]) -> String {
+    format!("| {} |\n", cells.join(" | "))
+}
+
+fn table_header(cells: &[String]) -> String {
+    let separator = cells.iter().map(|_| "---".to_string()).collect::<Vec<_>>();
+    table_row(cells) + &table_row(&separator)
+}
+
+fn environment(out: &mut String, env: &Environment, records: &[&Record]) {
+    let clocks = unique(records.iter().map(|r| (r.clock.as_str(), r.tick_frequency)));
+
+    out.push_str("| | |\n|---|---|\n");
+    let _ = writeln!(out, "| Device | {} |", env.device);
+    let _ = writeln!(out, "| Kernel | {} |", env.kernel);
+    let _ = writeln!(out, "| Machine | {} |", env.machine);
+    let _ = writeln!(out, "| Started | {} |", format_timestamp(env.timestamp));
+    for (clock, frequency) in clocks {
+        let _ = writeln!(out, "| Clock | {} ({:.2} MHz) |", clock, frequency / 1e6);
+    }
+}
+
+/// Table of the variants of `registry` that appear in `records`, in registry order
+fn variant_table(out: &mut String, records: &[Record], registry: &[Variant]) {
+    let variants = registry
+        .iter()
+        .filter(|v| records.iter().any(|r| r.variant == v.name))
+        .collect::<Vec<_>>();
+    if variants.is_empty() {
+        return;
+    }
+
+    let yes_no = |b: bool| if b { "Yes" } else { "No" }.to_string();
+
+    out.push_str("## Variants\n\n");
+    out.push_str(&table_header(
+        &[
+            "Variant",
+            "Instruction",
+            "Granule size",
+            "Implicit zero",
+            "memset",
+        ]
+        .map(String::from),
+    ));
+    for v in variants {
+        out.push_str(&table_row(&[
+            v.name.to_string(),
+            v.instruction
+                .map_or("-".to_string(), |i| format!("`{}`", i)),
+            v.granule.map_or("-".to_string(), |g| format!("`{}`", g)),
+            yes_no(v.implicit_zero),
+            yes_no(v.memset),
+        ]));
+    }
+    out.push('\n');
+}
+
+/// Tables of the mean time per variant and core, and the overhead relative to `memset`
+fn results_tables(out: &mut String, records: &[&Record], registry: &[Variant]) {
+    let cores = unique(records.iter().map(|r| r.core.as_str()));
+    let mut variants = unique(records.iter().map(|r| r.variant.as_str()));
+    // Registered variants first, in the order of the registry
+    variants.sort_by_key(|name| {
+        registry
+            .iter()
+            .position(|v| v.name == *name)
+            .unwrap_or(registry.len())
+    });
+
+    let find = |variant: &str, core: &str| {
+        records
+            .iter()
+            .find(|r| r.variant == variant && r.core == core)
+    };
+
+    let mut header = vec!["Variant".to_string()];
+    header.extend(cores.iter().map(|c| c.to_string()));
+
+    out.push_str(&table_header(&header));
+    let mut throttled = false;
+    for variant in &variants {
+        let mut row = vec![variant.to_string()];
+        for core in &cores {
+            row.push(match find(variant, core) {
+                Some(r) => {
+                    throttled |= r.throttled;
+                    format!(
+                        "{} ± {:.1}%{}",
+                        format_ns(r.mean_ns()),
+                        stats::cv(&r.samples_ns) * 100.0,
+                        if r.throttled { "\\*" } else { "" }
+                    )
+                }
+                None => "-".to_string(),
+            });
+        }
+        out.push_str(&table_row(&row));
+    }
+    if throttled {
+        out.push_str("\n\\* the CPU frequency dropped during the measurement\n");
+    }
+
+    if !variants.contains(&BASELINE) || variants.len() < 2 {
+        return;
+    }
+
+    let _ = writeln!(out, "\nOverhead relative to `{}`:\n", BASELINE);
+    out.push_str(&table_header(&header));
+    for variant in variants.iter().filter(|v| **v != BASELINE) {
+        let mut row = vec![variant.to_string()];
+        for core in &cores {
+            row.push(match (find(variant, core), find(BASELINE, core)) {
+                (Some(r), Some(base)) => {
+                    format!("{:+.1}%", (r.mean_ns() / base.mean_ns() - 1.0) * 100.0)
+                }
+                _ => "-".to_string(),
+            });
+        }
+        out.push_str(&table_row(&row));
+    }
+}
+
+/// Renders the records of a run as a Markdown document with the same tables as the README
+pub fn markdown(run: &str, records: &[Record]) -> String {
+    render(run, records, VARIANTS)
+}
+
+/// Renders the report, describing and ordering the variants as in `registry`
+fn render(run: &str, records: &[Record], registry: &[Variant]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# Results of run `{}`\n", run);
+
+    let environments = unique(records.iter().map(|r| &r.environment));
+    out.push_str("## Environment\n\n");
+    for env in environments {
+        let records = records
+            .iter()
+            .filter(|r| r.environment == *env)
+            .collect::<Vec<_>>();
+        environment(&mut out, env, &records);
+        out.push('\n');
+    }
+
+    variant_table(&mut out, records, registry);
+
+    let groups = unique(records.iter().map(|r| {
+        (
+            r.binary.as_str(),
+            r.size,
+            r.alloc.as_str(),
+            r.pages.as_str(),
+        )
+    }));
+    for (binary, size, alloc, pages) in groups {
+        let group = records
+            .iter()
+            .filter(|r| {
+                r.binary == binary && r.size == size && r.alloc == alloc && r.pages == pages
+            })
+            .collect::<Vec<_>>();
+
+        let _ = writeln!(
+            out,
+            "## {}: {}, {} allocation, {} pages\n",
+            binary,
+            format_size(size),
+            alloc,
+            pages
+        );
+        results_tables(&mut out, &group, registry);
+        out.push('\n');
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::tests::record;
+    use crate::results::ResultStore;
+
+    unsafe fn untouched(_mem: &mut [u8], _tag: u64) {}
+
+    /// Stands in for [`VARIANTS`], whose tagging kernels only build for aarch64
+    const REGISTRY: &[Variant] = &[
+        Variant {
+            name: "memset",
+            instruction: None,
+            granule: None,
+            implicit_zero: false,
+            memset: true,
+            features: &[],
+            run: untouched,
+        },
+        Variant {
+            name: "stg",
+            instruction: Some("stg"),
+            granule: Some(16),
+            implicit_zero: false,
+            memset: false,
+            features: &["mte"],
+            run: untouched,
+        },
+        Variant {
+            name: "stzg",
+            instruction: Some("stzg"),
+            granule: Some(16),
+            implicit_zero: true,
+            memset: false,
+            features: &["mte"],
+            run: untouched,
+        },
+        Variant {
+            name: "stgp",
+            instruction: Some("stgp"),
+            granule: Some(16),
+            implicit_zero: true,
+            memset: false,
+            features: &["mte"],
+            run: untouched,
+        },
+    ];
+
+    #[test]
+    fn renders_a_stored_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ResultStore::new(dir.path());
+        let mut throttled = record("run", "stzg", &[1500.0, 1500.0]);
+        throttled.throttled = true;
+        for record in [
+            record("run", "stg", &[1200.0, 1200.0]),
+            record("run", "memset", &[1000.0, 1000.0]),
+            throttled,
+        ] {
+            store.save(&record).unwrap();
+        }
+        // Another run in the same store is left out
+        store.save(&record("other", "stgp", &[900.0])).unwrap();
+
+        let report = render("run", &store.load_run("run").unwrap(), REGISTRY);
+
+        assert!(report.starts_with("# Results of run `run`\n"));
+        assert!(report.contains("## Environment\n\n"));
+        assert!(report.contains("| Device | test |\n"));
+        assert!(report.contains("| Clock | instant (1000.00 MHz) |\n"));
+
+        // The registry table lists the variants of the run in registry order
+        let variants = "## Variants\n\n\
+            | Variant | Instruction | Granule size | Implicit zero | memset |\n\
+            | --- | --- | --- | --- | --- |\n\
+            | memset | - | - | No | Yes |\n\
+            | stg | `stg` | `16` | No | No |\n\
+            | stzg | `stzg` | `16` | Yes | No |\n";
+        assert!(report.contains(variants), "{}", report);
+        assert!(!report.contains("stgp"));
+
+        assert!(report.contains("## stg: 4 KiB, fresh allocation, default pages\n"));
+        assert!(report.contains("| stg | 1.20 µs ± 0.0% |\n"));
+        assert!(report.contains("| stzg | 1.50 µs ± 0.0%\\* |\n"));
+        assert!(report.contains("\\* the CPU frequency dropped during the measurement\n"));
+        assert!(report.contains("Overhead relative to `memset`:"));
+        assert!(report.contains("| stg | +20.0% |\n"));
+        assert!(report.contains("| stzg | +50.0% |\n"));
+    }
+}
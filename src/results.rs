@@ -0,0 +1,511 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::harness::Measurement;
+use crate::plan::Slot;
+use crate::stats;
+
+/// Where the results of a run are stored
+#[derive(Args, Clone, Debug, Default)]
+pub struct ResultsConfig {
+    /// Store every measurement as JSON below this directory
+    #[arg(long = "results-dir")]
+    pub dir: Option<PathBuf>,
+    /// Name of this run in the results directory [default: the current UTC time, e.g. 2024-03-20T140512Z]
+    #[arg(long = "run-id")]
+    pub run: Option<String>,
+}
+
+/// The system a run was recorded on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Environment {
+    /// Device model, e.g. `Pixel 8`
+    pub device: String,
+    /// Kernel release and version from `uname`
+    pub kernel: String,
+    pub machine: String,
+    /// Unix time in seconds when the run started
+    pub timestamp: u64,
+}
+
+fn uname_field(field: &[libc::c_char]) -> String {
+    unsafe { std::ffi::CStr::from_ptr(field.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn device_model() -> Option<String> {
+    let getprop = std::process::Command::new("getprop")
+        .arg("ro.product.model")
+        .output()
+        .ok()
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .filter(|model| !model.is_empty());
+
+    getprop.or_else(|| {
+        ["/proc/device-tree/model", "/sys/class/dmi/id/product_name"]
+            .iter()
+            .find_map(|path| fs::read_to_string(path).ok())
+            .map(|model| {
+                model
+                    .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                    .to_string()
+            })
+            .filter(|model| !model.is_empty())
+    })
+}
+
+impl Environment {
+    pub fn capture() -> Self {
+        let mut uname: libc::utsname = unsafe { std::mem::zeroed() };
+        unsafe { libc::uname(&mut uname) };
+
+        Environment {
+            device: device_model().unwrap_or_else(|| uname_field(&uname.nodename)),
+            kernel: format!(
+                "{} {}",
+                uname_field(&uname.release),
+                uname_field(&uname.version)
+            ),
+            machine: uname_field(&uname.machine),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DDTHH:MM:SSZ`
+pub fn format_timestamp(secs: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// A measurement as stored in the results directory
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Record {
+    pub run: String,
+    /// Binary that produced the record, e.g. `stg`
+    pub binary: String,
+    /// Cluster label, e.g. `Cortex-X3-cpu8`
+    pub core: String,
+    pub variant: String,
+    pub size: usize,
+    pub alloc: String,
+    pub pages: String,
+    pub clock: String,
+    pub tick_frequency: f64,
+    pub samples_ticks: Vec<u64>,
+    pub samples_ns: Vec<f64>,
+    pub temp_start: Option<f64>,
+    pub temp_end: Option<f64>,
+    pub min_freq_khz: Option<u64>,
+    pub max_freq_khz: Option<u64>,
+    pub throttled: bool,
+    /// Counter values accumulated over all iterations
+    pub counters: BTreeMap<String, u64>,
+    pub faults: u64,
+    pub environment: Environment,
+    /// Round and position of the measurement if the variants were interleaved
+    pub slot: Option<Slot>,
+}
+
+impl Record {
+    pub fn new(run: &str, binary: &str, environment: &Environment, m: &Measurement) -> Self {
+        Record {
+            run: run.to_string(),
+            binary: binary.to_string(),
+            core: m.core.clone().unwrap_or_else(|| "unpinned".to_string()),
+            variant: m.name.clone(),
+            size: m.size,
+            alloc: m.alloc.name().to_string(),
+            pages: m.pages.name().to_string(),
+            clock: m.clock.to_string(),
+            tick_frequency: m.tick_frequency,
+            samples_ticks: m.samples.clone(),
+            samples_ns: m.durations().map(|d| d.as_nanos() as f64).collect(),
+            temp_start: m.temp_start,
+            temp_end: m.temp_end,
+            min_freq_khz: m.freq.as_ref().and_then(|f| f.min_khz()),
+            max_freq_khz: m.freq.as_ref().and_then(|f| f.max_observed_khz()),
+            throttled: m.throttled,
+            counters: m
+                .counters
+                .iter()
+                .map(|c| (c.counter.name().to_string(), c.value))
+                .collect(),
+            faults: m.faults,
+            environment: environment.clone(),
+            slot: m.slot,
+        }
+    }
+
+    pub fn mean_ns(&self) -> f64 {
+        stats::mean(&self.samples_ns)
+    }
+
+    /// Identifies the same measurement across runs
+    pub fn key(&self) -> RecordKey {
+        RecordKey {
+            device: self.environment.device.clone(),
+            core: self.core.clone(),
+            binary: self.binary.clone(),
+            variant: self.variant.clone(),
+            size: self.size,
+            alloc: self.alloc.clone(),
+            pages: self.pages.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecordKey {
+    pub device: String,
+    pub core: String,
+    pub binary: String,
+    pub variant: String,
+    pub size: usize,
+    pub alloc: String,
+    pub pages: String,
+}
+
+impl fmt::Display for RecordKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} [{}, {} pages, {} bytes]",
+            self.core, self.binary, self.variant, self.alloc, self.pages, self.size
+        )
+    }
+}
+
+/// Replaces characters that cannot appear in a path component
+fn sanitize(component: &str) -> String {
+    component
+        .chars()
+        .map(|c| if c == '/' || c == '\0' { '_' } else { c })
+        .collect()
+}
+
+/// A directory of JSON files laid out as `<device>/<core>/<variant>/<run>.json`
+pub struct ResultStore {
+    root: PathBuf,
+}
+
+impl ResultStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ResultStore { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, record: &Record) -> PathBuf {
+        self.root
+            .join(sanitize(&record.environment.device))
+            .join(sanitize(&record.core))
+            .join(sanitize(&record.variant))
+            .join(format!("{}.json", sanitize(&record.run)))
+    }
+
+    /// Adds `record` to the file of its run, variant, core and device
+    pub fn save(&self, record: &Record) -> io::Result<()> {
+        let path = self.path(record);
+        fs::create_dir_all(path.parent().expect("record path has a parent"))?;
+
+        let mut records = Self::read_file(&path).unwrap_or_default();
+        records.push(record.clone());
+
+        let json = serde_json::to_string_pretty(&records).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+
+    fn read_file(path: &Path) -> io::Result<Vec<Record>> {
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn files(&self) -> io::Result<Vec<PathBuf>> {
+        fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    walk(&path, files)?;
+                } else if path.extension().is_some_and(|ext| ext == "json") {
+                    files.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        walk(&self.root, &mut files)?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Names of all runs in the store, oldest first
+    pub fn runs(&self) -> io::Result<Vec<String>> {
+        let mut runs: Vec<(u64, String)> = Vec::new();
+        for file in self.files()? {
+            for record in Self::read_file(&file)? {
+                if !runs.iter().any(|(_, run)| *run == record.run) {
+                    runs.push((record.environment.timestamp, record.run));
+                }
+            }
+        }
+        runs.sort();
+        Ok(runs.into_iter().map(|(_, run)| run).collect())
+    }
+
+    /// All records of `run`
+    pub fn load_run(&self, run: &str) -> io::Result<Vec<Record>> {
+        let name = format!("{}.json", sanitize(run));
+        let mut records = Vec::new();
+        for file in self.files()? {
+            if file.file_name().is_some_and(|f| *f == *name) {
+                records.extend(Self::read_file(&file)?);
+            }
+        }
+
+        if records.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no results for run `{}` in {}", run, self.root.display()),
+            ));
+        }
+        Ok(records)
+    }
+}
+
+/// Combines the records of every round of an interleaved measurement into one record with the
+/// samples of all rounds, keeping records that were not interleaved as they are
+pub fn merge_rounds(records: Vec<Record>) -> Vec<Record> {
+    let mut merged: Vec<Record> = Vec::new();
+
+    for record in records {
+        let round = merged.iter_mut().find(|m| {
+            m.slot.is_some()
+                && record.slot.is_some()
+                && m.run == record.run
+                && m.key() == record.key()
+        });
+        match round {
+            Some(m) => {
+                m.samples_ticks.extend(&record.samples_ticks);
+                m.samples_ns.extend(&record.samples_ns);
+                m.temp_end = record.temp_end;
+                m.min_freq_khz = match (m.min_freq_khz, record.min_freq_khz) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+                m.max_freq_khz = m.max_freq_khz.max(record.max_freq_khz);
+                m.throttled |= record.throttled;
+                for (counter, value) in record.counters {
+                    *m.counters.entry(counter).or_default() += value;
+                }
+                m.faults += record.faults;
+            }
+            None => merged.push(record),
+        }
+    }
+
+    merged
+}
+
+/// Change of one measurement between two runs
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub key: RecordKey,
+    pub base_ns: f64,
+    pub new_ns: f64,
+    /// Relative change of the mean time, positive if the new run is slower
+    pub change: f64,
+    /// p-value of Welch's t-test on the per-iteration times
+    pub p_value: f64,
+}
+
+impl Comparison {
+    /// Whether the new run is significantly slower by more than `threshold`
+    pub fn is_regression(&self, threshold: f64, alpha: f64) -> bool {
+        self.change > threshold && self.p_value < alpha
+    }
+
+    pub fn is_improvement(&self, threshold: f64, alpha: f64) -> bool {
+        self.change < -threshold && self.p_value < alpha
+    }
+}
+
+/// Outcome of comparing two runs
+#[derive(Clone, Debug, Default)]
+pub struct RunComparison {
+    /// Measurements found in both runs, sorted by key
+    pub comparisons: Vec<Comparison>,
+    /// Measurements of the base run that are missing from the new run
+    pub missing: Vec<RecordKey>,
+}
+
+/// The records of `run` by key, failing if a key appears more than once
+fn by_key<'a>(run: &str, records: &'a [Record]) -> Result<BTreeMap<RecordKey, &'a Record>, String> {
+    let mut keyed = BTreeMap::new();
+    for record in records {
+        if keyed.insert(record.key(), record).is_some() {
+            return Err(format!(
+                "the {} run has more than one record for {}, e.g. from running a binary twice with \
+                 the same --run-id",
+                run,
+                record.key()
+            ));
+        }
+    }
+    Ok(keyed)
+}
+
+/// Pairs up the records of two runs by [`RecordKey`] and compares their timings.
+///
+/// The rounds of interleaved measurements have to be merged with [`merge_rounds`] first, any
+/// other key that appears twice in one run is an error.
+pub fn compare(base: &[Record], new: &[Record]) -> Result<RunComparison, String> {
+    let base = by_key("base", base)?;
+    let new = by_key("new", new)?;
+
+    let comparisons = new
+        .iter()
+        .filter_map(|(key, new)| {
+            let base = base.get(key)?;
+            let (base_ns, new_ns) = (base.mean_ns(), new.mean_ns());
+
+            Some(Comparison {
+                key: key.clone(),
+                base_ns,
+                new_ns,
+                change: new_ns / base_ns - 1.0,
+                p_value: stats::welch_t_test(&base.samples_ns, &new.samples_ns),
+            })
+        })
+        .collect();
+    let missing = base
+        .keys()
+        .filter(|key| !new.contains_key(key))
+        .cloned()
+        .collect();
+
+    Ok(RunComparison {
+        comparisons,
+        missing,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A record of `variant` on a fixed core and size, for the tests of the modules that
+    /// analyse records
+    pub(crate) fn record(run: &str, variant: &str, samples_ns: &[f64]) -> Record {
+        Record {
+            run: run.to_string(),
+            binary: "stg".to_string(),
+            core: "Cortex-A510-cpu0".to_string(),
+            variant: variant.to_string(),
+            size: 4096,
+            alloc: "fresh".to_string(),
+            pages: "default".to_string(),
+            clock: "instant".to_string(),
+            tick_frequency: 1e9,
+            samples_ticks: samples_ns.iter().map(|&ns| ns as u64).collect(),
+            samples_ns: samples_ns.to_vec(),
+            temp_start: None,
+            temp_end: None,
+            min_freq_khz: None,
+            max_freq_khz: None,
+            throttled: false,
+            counters: BTreeMap::new(),
+            faults: 0,
+            environment: Environment {
+                device: "test".to_string(),
+                kernel: "6.6".to_string(),
+                machine: "aarch64".to_string(),
+                timestamp: 0,
+            },
+            slot: None,
+        }
+    }
+
+    #[test]
+    fn compare_pairs_records_by_key() {
+        let base = [
+            record("a", "stg", &[100.0, 101.0]),
+            record("a", "stzg", &[200.0, 201.0]),
+        ];
+        let new = [
+            record("b", "stzg", &[300.0, 302.0]),
+            record("b", "stg", &[100.0, 101.0]),
+        ];
+
+        let result = compare(&base, &new).unwrap();
+        let variants = result
+            .comparisons
+            .iter()
+            .map(|c| c.key.variant.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(variants, ["stg", "stzg"]);
+        assert!(result.comparisons[0].change.abs() < 1e-12);
+        assert!(result.comparisons[1].change > 0.49);
+        assert!(result.missing.is_empty());
+    }
+
+    #[test]
+    fn compare_reports_missing_records() {
+        let base = [
+            record("a", "stg", &[100.0, 101.0]),
+            record("a", "stzg", &[200.0, 201.0]),
+        ];
+        let new = [record("b", "stg", &[100.0, 101.0])];
+
+        let result = compare(&base, &new).unwrap();
+        assert_eq!(result.comparisons.len(), 1);
+        assert_eq!(result.missing.len(), 1);
+        assert_eq!(result.missing[0].variant, "stzg");
+    }
+
+    #[test]
+    fn compare_rejects_duplicate_keys() {
+        let base = [record("a", "stg", &[100.0, 101.0])];
+        let new = [
+            record("b", "stg", &[100.0, 101.0]),
+            record("b", "stg", &[150.0, 151.0]),
+        ];
+
+        assert!(compare(&base, &new).is_err());
+        assert!(compare(&new, &base).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use clap::Parser;
+use mte_measurement::chunks::{layout, ChunkVariant, SizeDistribution};
+use mte_measurement::cpu::discover_clusters;
+use mte_measurement::harness::{throughput_table, Harness, HarnessConfig};
+use mte_measurement::{set_mte_mode, MTEMode};
+use std::hint::black_box;
+
+const ITERS: u64 = 50;
+
+#[derive(Parser)]
+struct Args {
+    /// Distribution of allocation sizes: fixed:<size>, uniform:<min>-<max> or file:<path> with
+    /// one `size weight` pair per line
+    #[arg(long, default_value = "uniform:16-4096")]
+    sizes: SizeDistribution,
+    /// Number of allocations tagged per iteration
+    #[arg(long, default_value_t = 10000)]
+    count: usize,
+    /// Zero every allocation, with memset for variants that do not zero while tagging
+    #[arg(long)]
+    zero: bool,
+    /// Variants to measure, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = ChunkVariant::ALL)]
+    variant: Vec<ChunkVariant>,
+    #[command(flatten)]
+    harness: HarnessConfig,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    unsafe {
+        set_mte_mode(MTEMode::Sync);
+    }
+
+    let (chunks, size) = layout(&args.sizes, args.count);
+    let size = size.next_multiple_of(4096);
+    println!(
+        "{} allocations from {} in {} KiB{}",
+        chunks.len(),
+        args.sizes,
+        size / 1024,
+        if args.zero { ", zeroed" } else { "" }
+    );
+
+    let (variants, zero) = (args.variant, args.zero);
+    let mut harness = Harness::new(args.harness);
+    let page_policies = harness.page_policies();
+
+    for cluster in discover_clusters() {
+        harness.pin(&cluster).expect("could not pin to cpu");
+
+        let mut measurements = Vec::new();
+
+        for &pages in &page_policies {
+            harness.set_page_policy(pages);
+
+            let mut results = Vec::new();
+
+            for &variant in &variants {
+                let measurement = harness.measure_validated(
+                    variant.name(),
+                    size,
+                    ITERS,
+                    |mem| {
+                        for chunk in &chunks {
+                            unsafe { variant.allocate(black_box(&mut *mem), chunk, zero) };
+                        }
+                    },
+                    |mem| {
+                        chunks
+                            .iter()
+                            .try_for_each(|chunk| unsafe { variant.validate(mem, chunk, zero) })
+                    },
+                );
+                let per_alloc = measurement.mean().as_nanos() as f64 / chunks.len() as f64;
+                println!("{}\n    {:.1} ns per allocation", measurement, per_alloc);
+                results.push(per_alloc);
+                measurements.push(measurement);
+            }
+
+            let result = results
+                .iter()
+                .map(|ns| format!("{:.1}", ns))
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!(
+                "{} with {} pages: [{}] ns per allocation",
+                cluster,
+                pages.name(),
+                result
+            );
+        }
+
+        println!("{}", throughput_table(&measurements));
+    }
+}
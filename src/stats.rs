@@ -0,0 +1,188 @@
+pub fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Sample variance (with Bessel's correction)
+pub fn variance(samples: &[f64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(samples);
+    samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64
+}
+
+pub fn std_dev(samples: &[f64]) -> f64 {
+    variance(samples).sqrt()
+}
+
+/// Coefficient of variation, i.e. standard deviation relative to the mean
+pub fn cv(samples: &[f64]) -> f64 {
+    std_dev(samples) / mean(samples)
+}
+
+/// Two-sided p-value of Welch's t-test for the hypothesis that `a` and `b` have the same mean
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() < 2 || b.len() < 2 {
+        return 1.0;
+    }
+
+    let (va, vb) = (variance(a) / a.len() as f64, variance(b) / b.len() as f64);
+    if va + vb == 0.0 {
+        return if mean(a) == mean(b) { 1.0 } else { 0.0 };
+    }
+
+    let t = (mean(a) - mean(b)) / (va + vb).sqrt();
+    let df =
+        (va + vb).powi(2) / (va.powi(2) / (a.len() - 1) as f64 + vb.powi(2) / (b.len() - 1) as f64);
+
+    student_t_p_value(t, df)
+}
+
+/// Two-sided p-value of `t` under Student's t-distribution with `df` degrees of freedom
+fn student_t_p_value(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// Natural logarithm of the gamma function (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| {
+            sum + c / (x + 1.0 + i as f64)
+        });
+
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+
+    // The continued fraction converges quickly only for x < (a + 1) / (a + b + 2)
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction for the incomplete beta function (modified Lentz's method)
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPSILON: f64 = 3e-16;
+    const TINY: f64 = 1e-300;
+
+    let clamp = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+
+        let numerator = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / clamp(1.0 + numerator * d);
+        c = clamp(1.0 + numerator / c);
+        h *= d * c;
+
+        let numerator = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / clamp(1.0 + numerator * d);
+        c = clamp(1.0 + numerator / c);
+        let delta = d * c;
+        h *= delta;
+
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn ln_gamma_matches_factorials() {
+        assert_close(ln_gamma(1.0), 0.0, 1e-10);
+        assert_close(ln_gamma(5.0), 24f64.ln(), 1e-10);
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln(), 1e-10);
+    }
+
+    #[test]
+    fn incomplete_beta_bounds_and_symmetry() {
+        assert_eq!(incomplete_beta(2.0, 3.0, 0.0), 0.0);
+        assert_eq!(incomplete_beta(2.0, 3.0, 1.0), 1.0);
+        // I_x(1, 1) is the uniform distribution
+        assert_close(incomplete_beta(1.0, 1.0, 0.3), 0.3, 1e-12);
+        // I_x(a, b) = 1 - I_(1-x)(b, a)
+        assert_close(
+            incomplete_beta(2.5, 4.0, 0.2),
+            1.0 - incomplete_beta(4.0, 2.5, 0.8),
+            1e-12,
+        );
+    }
+
+    #[test]
+    fn student_t_p_values() {
+        // Two-sided values from tables of Student's t-distribution
+        assert_close(student_t_p_value(2.0, 10.0), 0.0734, 1e-4);
+        assert_close(student_t_p_value(2.228, 10.0), 0.05, 1e-4);
+        assert_close(student_t_p_value(1.0, 8.0), 0.3466, 1e-4);
+        assert_close(student_t_p_value(-2.0, 10.0), 0.0734, 1e-4);
+        assert_close(student_t_p_value(0.0, 5.0), 1.0, 1e-12);
+        assert_close(student_t_p_value(1.96, 1e6), 0.05, 1e-4);
+    }
+
+    #[test]
+    fn welch_t_test_of_shifted_samples() {
+        // t = -1 with 8 degrees of freedom
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_close(welch_t_test(&a, &b), 0.3466, 1e-4);
+        assert_close(welch_t_test(&a, &a), 1.0, 1e-12);
+    }
+
+    #[test]
+    fn welch_t_test_without_variance() {
+        assert_eq!(welch_t_test(&[3.0, 3.0, 3.0], &[3.0, 3.0]), 1.0);
+        assert_eq!(welch_t_test(&[3.0, 3.0, 3.0], &[4.0, 4.0]), 0.0);
+    }
+
+    #[test]
+    fn welch_t_test_needs_two_samples() {
+        assert_eq!(welch_t_test(&[1.0], &[5.0, 6.0, 7.0]), 1.0);
+    }
+}
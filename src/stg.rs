@@ -1,66 +1,72 @@
 use std::hint::black_box;
-use rand::random;
-use mte_measurement::{memset, MTEMode, set_mte_mode, st2g, st2g_zero, stg, stg_zero, stgp, stz2g, stzg};
+use std::time::Duration;
+use clap::Parser;
+use mte_measurement::cpu::discover_clusters;
+use mte_measurement::harness::{throughput_table, Harness, HarnessConfig};
+use mte_measurement::plan::{Plan, PlanConfig};
+use mte_measurement::variant::supported;
+use mte_measurement::{MTEMode, set_mte_mode};
 
 // 128 MiB
 const SIZE: usize = 128 * 1024 * 1024;
 
-const ITERS: u32 = 50;
+const ITERS: u64 = 50;
 
-fn measure_custom(iters: u64, f: impl Fn(&mut [u8]) -> ()) -> std::time::Duration {
-    let mut result = std::time::Duration::from_secs(0);
-
-    for _ in 0..iters {
-        let mem = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                SIZE,
-                libc::PROT_READ | libc::PROT_WRITE | 0x20,
-                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
-                -1,
-                0,
-            )
-        };
-
-        assert_ne!(mem, libc::MAP_FAILED);
-        let mut mem_slice = unsafe { std::slice::from_raw_parts_mut(mem as *mut u8, SIZE) };
-
-        let start = std::time::Instant::now();
-        f(&mut mem_slice);
-        result += start.elapsed();
-
-        unsafe { libc::munmap(mem, SIZE) };
-    }
-
-    result
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    harness: HarnessConfig,
+    #[command(flatten)]
+    plan: PlanConfig,
 }
 
 fn main() {
+    let args = Args::parse();
+
     unsafe {
         set_mte_mode(MTEMode::Sync);
     }
-    let fns: [(&'static str, Box<dyn Fn(&mut [u8]) -> ()>); 8] = [
-        ("memset", Box::new(|mem| unsafe { memset(black_box(mem)) })),
-        ("stg", Box::new(|mem| unsafe { stg(black_box(mem), black_box(random())) })),
-        ("stgp", Box::new(|mem| unsafe { stgp(black_box(mem), black_box(random())) })),
-        ("st2g", Box::new(|mem| unsafe { st2g(black_box(mem), black_box(random())) })),
-        ("stzg", Box::new(|mem| unsafe { stzg(black_box(mem), black_box(random())) })),
-        ("stz2g", Box::new(|mem| unsafe { stz2g(black_box(mem), black_box(random())) })),
-        ("stg+memset", Box::new(|mem| unsafe { stg_zero(black_box(mem), black_box(random())) })),
-        ("st2g+memset", Box::new(|mem| unsafe { st2g_zero(black_box(mem), black_box(random())) })),
-    ];
+    let variants = supported().collect::<Vec<_>>();
 
-    let mut results = Vec::new();
+    let plan = Plan::new(&args.plan, variants.len());
+    let names = variants.iter().map(|variant| variant.name).collect::<Vec<_>>();
+    println!("variant order {}", plan.describe(&names));
 
-    for (_, f) in fns.iter() {
-        let result = measure_custom(ITERS.into(), f);
-        results.push(result / ITERS);
-        std::thread::sleep(std::time::Duration::from_secs(15));
-    }
+    let mut harness = Harness::new(args.harness);
+    harness.set_rounds(plan.rounds.len());
+    let page_policies = harness.page_policies();
 
-    let result = results.iter().map(|result| result.as_millis())
-        .map(|m| m.to_string())
-        .collect::<Vec<_>>()
-        .join(", ");
-    println!("[{}]", result);
+    for cluster in discover_clusters() {
+        harness.pin(&cluster).expect("could not pin to cpu");
+
+        let mut measurements = Vec::new();
+
+        for &pages in &page_policies {
+            harness.set_page_policy(pages);
+
+            let mut results = vec![Vec::new(); variants.len()];
+
+            for (slot, i) in plan.slots() {
+                let variant = variants[i];
+                harness.set_slot(Some(slot));
+                let measurement = harness.measure_variant(variant, SIZE, plan.iters_per_round(ITERS), |mem, tag| unsafe {
+                    (variant.run)(black_box(mem), black_box(tag))
+                });
+                println!("{}", measurement);
+                results[i].push(measurement.mean());
+                measurements.push(measurement);
+            }
+
+            // Mean over all rounds, in the order of the registry
+            let result = results.iter()
+                .map(|rounds| rounds.iter().sum::<Duration>() / rounds.len() as u32)
+                .map(|result| result.as_millis())
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("{} with {} pages: [{}]", cluster, pages.name(), result);
+        }
+
+        println!("{}", throughput_table(&measurements));
+    }
 }
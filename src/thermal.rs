@@ -0,0 +1,290 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::Args;
+
+const SYSFS_THERMAL: &str = "/sys/class/thermal";
+
+/// The `temp` files of the thermal zones used to judge whether the device has cooled down
+#[derive(Clone, Debug)]
+pub struct ThermalZones {
+    zones: Vec<PathBuf>,
+}
+
+impl ThermalZones {
+    /// Discovers all thermal zones whose type contains one of `types` (all zones if empty)
+    pub fn discover(types: &[String]) -> Self {
+        Self::discover_in(Path::new(SYSFS_THERMAL), types)
+    }
+
+    /// Discovers thermal zones below `root` (normally `/sys/class/thermal`)
+    pub fn discover_in(root: &Path, types: &[String]) -> Self {
+        let mut zones = fs::read_dir(root)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_name().to_string_lossy().starts_with("thermal_zone"))
+                    .map(|entry| entry.path())
+                    .filter(|zone| {
+                        let zone_type = fs::read_to_string(zone.join("type")).unwrap_or_default();
+                        types.is_empty() || types.iter().any(|t| zone_type.contains(t.as_str()))
+                    })
+                    .map(|zone| zone.join("temp"))
+                    .filter(|temp| read_millidegrees(temp).is_some())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        zones.sort();
+
+        ThermalZones { zones }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    /// Temperature of the hottest zone in °C
+    pub fn max_temp(&self) -> Option<f64> {
+        self.zones
+            .iter()
+            .filter_map(|zone| read_millidegrees(zone))
+            .max()
+            .map(|m| m as f64 / 1000.0)
+    }
+}
+
+fn read_millidegrees(path: &Path) -> Option<i64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// When the device counts as cooled down between two measurements
+#[derive(Args, Clone, Debug)]
+pub struct CooldownConfig {
+    /// Wait until the hottest thermal zone is at or below this temperature (°C)
+    #[arg(long = "cooldown-threshold")]
+    pub threshold: Option<f64>,
+    /// Without a threshold, wait until the temperature is within this many °C of the temperature at startup
+    #[arg(long = "cooldown-delta", default_value_t = 2.0)]
+    pub delta: f64,
+    /// Give up waiting after this many seconds
+    #[arg(long = "cooldown-timeout", default_value_t = 120)]
+    pub timeout_secs: u64,
+    /// Interval between two temperature readings in milliseconds
+    #[arg(long = "cooldown-poll", default_value_t = 500)]
+    pub poll_ms: u64,
+    /// Fixed sleep in seconds if no thermal zone can be read
+    #[arg(long = "cooldown-fallback", default_value_t = 15)]
+    pub fallback_secs: u64,
+    /// Only consider thermal zones whose type contains this string (can be repeated)
+    #[arg(long = "thermal-zone")]
+    pub zones: Vec<String>,
+}
+
+impl Default for CooldownConfig {
+    fn default() -> Self {
+        CooldownConfig {
+            threshold: None,
+            delta: 2.0,
+            timeout_secs: 120,
+            poll_ms: 500,
+            fallback_secs: 15,
+            zones: Vec::new(),
+        }
+    }
+}
+
+/// Waits between measurements until the device is cool enough
+pub struct Cooldown {
+    zones: ThermalZones,
+    config: CooldownConfig,
+    baseline: Option<f64>,
+}
+
+impl Cooldown {
+    /// Creates a cooldown controller, using the current temperature as the baseline
+    pub fn new(config: CooldownConfig) -> Self {
+        Self::with_zones(ThermalZones::discover(&config.zones), config)
+    }
+
+    pub fn with_zones(zones: ThermalZones, config: CooldownConfig) -> Self {
+        let baseline = zones.max_temp();
+        Cooldown {
+            zones,
+            config,
+            baseline,
+        }
+    }
+
+    /// Current temperature of the hottest zone in °C
+    pub fn temperature(&self) -> Option<f64> {
+        self.zones.max_temp()
+    }
+
+    /// The temperature that has to be reached before the next measurement starts
+    pub fn target(&self) -> Option<f64> {
+        self.config
+            .threshold
+            .or(self.baseline.map(|baseline| baseline + self.config.delta))
+    }
+
+    /// Blocks until the target temperature is reached or the timeout expires.
+    ///
+    /// Falls back to a fixed sleep if no thermal zone is available. Returns the time spent waiting.
+    pub fn wait(&self) -> Duration {
+        let start = Instant::now();
+
+        let Some(target) = self.target() else {
+            let fallback = Duration::from_secs(self.config.fallback_secs);
+            std::thread::sleep(fallback);
+            return fallback;
+        };
+
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let poll = Duration::from_millis(self.config.poll_ms);
+
+        loop {
+            match self.temperature() {
+                Some(temp) if temp <= target => break,
+                temp => {
+                    if start.elapsed() >= timeout {
+                        eprintln!(
+                            "cooldown timed out after {:?} at {:.1} °C (target {:.1} °C)",
+                            timeout,
+                            temp.unwrap_or(f64::NAN),
+                            target
+                        );
+                        break;
+                    }
+                }
+            }
+            std::thread::sleep(poll);
+        }
+
+        start.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A thermal sysfs tree with a zone of each `(type, millidegrees)`, `None` for a zone
+    /// without a readable temperature
+    fn fake_sysfs(zones: &[(&str, Option<&str>)]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for (i, (zone_type, temp)) in zones.iter().enumerate() {
+            let zone = root.path().join(format!("thermal_zone{}", i));
+            fs::create_dir(&zone).unwrap();
+            fs::write(zone.join("type"), format!("{}\n", zone_type)).unwrap();
+            if let Some(temp) = temp {
+                fs::write(zone.join("temp"), format!("{}\n", temp)).unwrap();
+            }
+        }
+        // Cooling devices live next to the zones and are not zones themselves
+        let cooling = root.path().join("cooling_device0");
+        fs::create_dir(&cooling).unwrap();
+        fs::write(cooling.join("type"), "cpu-big\n").unwrap();
+        fs::write(cooling.join("temp"), "99000\n").unwrap();
+        root
+    }
+
+    fn config(threshold: Option<f64>) -> CooldownConfig {
+        CooldownConfig {
+            threshold,
+            timeout_secs: 0,
+            poll_ms: 1,
+            fallback_secs: 0,
+            ..CooldownConfig::default()
+        }
+    }
+
+    #[test]
+    fn discovers_zones_by_type() {
+        let root = fake_sysfs(&[
+            ("cpu-big-thermal", Some("45500")),
+            ("battery", Some("30000")),
+            ("cpu-little-thermal", Some("garbage")),
+            ("cpu-mid-thermal", None),
+        ]);
+
+        let all = ThermalZones::discover_in(root.path(), &[]);
+        assert_eq!(all.zones.len(), 2);
+        assert_eq!(all.max_temp(), Some(45.5));
+
+        let battery = ThermalZones::discover_in(root.path(), &["battery".to_string()]);
+        assert_eq!(battery.max_temp(), Some(30.0));
+
+        let cpu = ThermalZones::discover_in(root.path(), &["cpu".to_string()]);
+        assert_eq!(cpu.zones.len(), 1);
+        assert_eq!(cpu.max_temp(), Some(45.5));
+
+        let none = ThermalZones::discover_in(root.path(), &["gpu".to_string()]);
+        assert!(none.is_empty());
+        assert_eq!(none.max_temp(), None);
+    }
+
+    #[test]
+    fn missing_sysfs_has_no_zones() {
+        let root = tempfile::tempdir().unwrap();
+        let zones = ThermalZones::discover_in(&root.path().join("missing"), &[]);
+        assert!(zones.is_empty());
+    }
+
+    #[test]
+    fn target_defaults_to_baseline_plus_delta() {
+        let root = fake_sysfs(&[("cpu", Some("40000"))]);
+        let zones = ThermalZones::discover_in(root.path(), &[]);
+
+        let cooldown = Cooldown::with_zones(zones.clone(), config(None));
+        assert_eq!(cooldown.target(), Some(42.0));
+
+        let cooldown = Cooldown::with_zones(zones, config(Some(35.0)));
+        assert_eq!(cooldown.target(), Some(35.0));
+    }
+
+    #[test]
+    fn wait_returns_once_below_threshold() {
+        let root = fake_sysfs(&[("cpu", Some("40000"))]);
+        let zones = ThermalZones::discover_in(root.path(), &[]);
+        let cooldown = Cooldown::with_zones(
+            zones,
+            CooldownConfig {
+                timeout_secs: 10,
+                ..config(Some(35.0))
+            },
+        );
+
+        let zone = root.path().join("thermal_zone0");
+        let cooler = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            fs::write(zone.join("temp"), "34000\n").unwrap();
+        });
+
+        let waited = cooldown.wait();
+        cooler.join().unwrap();
+        assert!(waited >= Duration::from_millis(50));
+        assert!(waited < Duration::from_secs(10));
+        assert!(cooldown.temperature().unwrap() <= 35.0);
+    }
+
+    #[test]
+    fn wait_gives_up_after_timeout() {
+        let root = fake_sysfs(&[("cpu", Some("80000"))]);
+        let zones = ThermalZones::discover_in(root.path(), &[]);
+        let cooldown = Cooldown::with_zones(zones, config(Some(35.0)));
+
+        assert!(cooldown.wait() < Duration::from_secs(1));
+        assert_eq!(cooldown.temperature(), Some(80.0));
+    }
+
+    #[test]
+    fn wait_sleeps_without_zones() {
+        let root = tempfile::tempdir().unwrap();
+        let zones = ThermalZones::discover_in(root.path(), &[]);
+        let cooldown = Cooldown::with_zones(zones, config(None));
+
+        assert_eq!(cooldown.target(), None);
+        assert_eq!(cooldown.wait(), Duration::ZERO);
+    }
+}
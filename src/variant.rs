@@ -0,0 +1,436 @@
+use crate::{
+    load_tag, micro, set_tag, st2g, st2g_zero, stg, stg_prefetch, stg_zero, stgp, stz2g, stzg,
+};
+
+const TAG_MASK: u64 = 0x0f00_0000_0000_0000;
+
+/// A way of tagging and possibly zeroing a region of memory
+#[derive(Debug)]
+pub struct Variant {
+    pub name: &'static str,
+    /// Instruction that sets the tags, `None` if the variant does not tag memory
+    pub instruction: Option<&'static str>,
+    /// Bytes tagged by one instruction
+    pub granule: Option<usize>,
+    /// Whether the instruction zeroes the memory it tags
+    pub implicit_zero: bool,
+    /// Whether the memory is zeroed with a separate `memset`
+    pub memset: bool,
+    /// CPU features the variant needs, as named by `is_aarch64_feature_detected!`
+    pub features: &'static [&'static str],
+    /// Runs the variant on memory mapped with `PROT_MTE` whose length is a multiple of 32 bytes,
+    /// tagging it with the tag in bits 56-59
+    pub run: unsafe fn(&mut [u8], u64),
+}
+
+impl Variant {
+    /// Whether the memory is zero after running the variant
+    pub fn zeroes(&self) -> bool {
+        self.implicit_zero || self.memset
+    }
+
+    /// The first of the required features this CPU does not have, if any
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        self.features
+            .iter()
+            .copied()
+            .find(|&feature| !detected(feature))
+    }
+
+    /// Checks that `mem` holds what the variant leaves behind after running with `tag`: that tag
+    /// in every granule (or the untouched tag 0 if it does not tag) and zeros if it zeroes.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must be mapped with `PROT_MTE` and aligned to 16 bytes.
+    pub unsafe fn validate(&self, mem: &[u8], tag: u64) -> Result<(), String> {
+        let expected = match self.instruction {
+            Some(_) => tag & TAG_MASK,
+            None => 0,
+        };
+
+        for offset in (0..mem.len()).step_by(16) {
+            let granule = mem.as_ptr().add(offset);
+            let actual = load_tag(granule);
+            if actual != expected {
+                return Err(format!(
+                    "{} left tag {:#x} instead of {:#x} at offset {}",
+                    self.name,
+                    actual >> 56,
+                    expected >> 56,
+                    offset
+                ));
+            }
+
+            let data = std::slice::from_raw_parts(set_tag(granule as *mut u8, actual), 16);
+            if self.zeroes() && data.iter().any(|&byte| byte != 0) {
+                return Err(format!(
+                    "{} did not zero the granule at offset {}",
+                    self.name, offset
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub const VARIANTS: &[Variant] = &[
+    Variant {
+        name: "memset",
+        instruction: None,
+        granule: None,
+        implicit_zero: false,
+        memset: true,
+        features: &[],
+        run: memset,
+    },
+    Variant {
+        name: "stg",
+        instruction: Some("stg"),
+        granule: Some(16),
+        implicit_zero: false,
+        memset: false,
+        features: &["mte"],
+        run: stg,
+    },
+    Variant {
+        name: "stg+prefetch",
+        instruction: Some("stg"),
+        granule: Some(16),
+        implicit_zero: false,
+        memset: false,
+        features: &["mte"],
+        run: stg_prefetch,
+    },
+    Variant {
+        name: "stgp",
+        instruction: Some("stgp"),
+        granule: Some(16),
+        implicit_zero: true,
+        memset: false,
+        features: &["mte"],
+        run: stgp,
+    },
+    Variant {
+        name: "st2g",
+        instruction: Some("st2g"),
+        granule: Some(32),
+        implicit_zero: false,
+        memset: false,
+        features: &["mte"],
+        run: st2g,
+    },
+    Variant {
+        name: "stzg",
+        instruction: Some("stzg"),
+        granule: Some(16),
+        implicit_zero: true,
+        memset: false,
+        features: &["mte"],
+        run: stzg,
+    },
+    Variant {
+        name: "stz2g",
+        instruction: Some("stz2g"),
+        granule: Some(32),
+        implicit_zero: true,
+        memset: false,
+        features: &["mte"],
+        run: stz2g,
+    },
+    Variant {
+        name: "stg+memset",
+        instruction: Some("stg"),
+        granule: Some(16),
+        implicit_zero: false,
+        memset: true,
+        features: &["mte"],
+        run: stg_zero,
+    },
+    Variant {
+        name: "st2g+memset",
+        instruction: Some("st2g"),
+        granule: Some(32),
+        implicit_zero: false,
+        memset: true,
+        features: &["mte"],
+        run: st2g_zero,
+    },
+];
+
+/// Zeroes the memory without tagging it
+unsafe fn memset(mem: &mut [u8], _tag: u64) {
+    crate::memset(mem)
+}
+
+/// Whether this CPU has `feature`, panicking on names no variant is supposed to use
+fn detected(feature: &str) -> bool {
+    match feature {
+        #[cfg(target_arch = "aarch64")]
+        "mte" => std::arch::is_aarch64_feature_detected!("mte"),
+        #[cfg(not(target_arch = "aarch64"))]
+        "mte" => false,
+        _ => panic!("unknown CPU feature {:?}", feature),
+    }
+}
+
+/// The variants this CPU can run, reporting the skipped ones
+pub fn supported() -> impl Iterator<Item = &'static Variant> {
+    VARIANTS
+        .iter()
+        .filter(|variant| match variant.missing_feature() {
+            Some(feature) => {
+                eprintln!(
+                    "skipping {}: the CPU does not support {}",
+                    variant.name, feature
+                );
+                false
+            }
+            None => true,
+        })
+}
+
+/// Looks up a variant by name
+pub fn find(name: &str) -> Option<&'static Variant> {
+    VARIANTS.iter().find(|v| v.name == name)
+}
+
+/// What a microbenchmark measures
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MicroKind {
+    /// Time until the result of an instruction is available to the next one
+    Latency,
+    /// Instructions completed per unit of time when they are independent
+    Throughput,
+}
+
+impl MicroKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MicroKind::Latency => "latency",
+            MicroKind::Throughput => "throughput",
+        }
+    }
+}
+
+/// A microbenchmark of a single instruction
+#[derive(Debug)]
+pub struct Micro {
+    pub instruction: &'static str,
+    pub kind: MicroKind,
+    /// Instructions per loop iteration of throughput kernels that are unrolled explicitly
+    pub unroll: Option<usize>,
+    /// Instructions executed per round
+    pub ops: u64,
+    /// Runs the given number of rounds.
+    ///
+    /// Has to be called with a pointer to at least [`micro::MEM_SIZE`] bytes of zeroed memory
+    /// mapped with `PROT_MTE`.
+    pub run: unsafe fn(*mut u8, u64),
+}
+
+impl Micro {
+    const fn latency(instruction: &'static str, ops: u64, run: unsafe fn(*mut u8, u64)) -> Self {
+        Micro {
+            instruction,
+            kind: MicroKind::Latency,
+            unroll: None,
+            ops,
+            run,
+        }
+    }
+
+    const fn throughput(instruction: &'static str, run: unsafe fn(*mut u8, u64)) -> Self {
+        Micro {
+            instruction,
+            kind: MicroKind::Throughput,
+            unroll: None,
+            ops: micro::OPS,
+            run,
+        }
+    }
+
+    /// A throughput kernel tagging [`micro::STORE_SIZE`] bytes per round in `granule` sized steps
+    const fn unrolled(
+        instruction: &'static str,
+        granule: usize,
+        unroll: usize,
+        run: unsafe fn(*mut u8, u64),
+    ) -> Self {
+        Micro {
+            instruction,
+            kind: MicroKind::Throughput,
+            unroll: Some(unroll),
+            ops: (micro::STORE_SIZE / granule) as u64,
+            run,
+        }
+    }
+
+    /// Name of the benchmark, e.g. `irg/latency` or `stg/throughput/x4`
+    pub fn name(&self) -> String {
+        match self.unroll {
+            Some(unroll) => format!("{}/{}/x{}", self.instruction, self.kind.name(), unroll),
+            None => format!("{}/{}", self.instruction, self.kind.name()),
+        }
+    }
+}
+
+pub const MICRO: &[Micro] = &[
+    Micro::latency("irg", micro::OPS, micro::irg_latency),
+    Micro::throughput("irg", micro::irg_throughput),
+    Micro::latency("addg", micro::OPS, micro::addg_latency),
+    Micro::throughput("addg", micro::addg_throughput),
+    Micro::latency("subg", micro::OPS, micro::subg_latency),
+    Micro::throughput("subg", micro::subg_throughput),
+    Micro::latency("gmi", micro::OPS, micro::gmi_latency),
+    Micro::throughput("gmi", micro::gmi_throughput),
+    Micro::latency("ldg", micro::OPS, micro::ldg_latency),
+    Micro::throughput("ldg", micro::ldg_throughput),
+    // One round of a tag store latency kernel retags, stores and loads a single granule
+    Micro::latency("stg", 1, micro::stg_latency),
+    Micro::unrolled("stg", 16, 1, micro::stg_x1),
+    Micro::unrolled("stg", 16, 2, micro::stg_x2),
+    Micro::unrolled("stg", 16, 4, micro::stg_x4),
+    Micro::unrolled("stg", 16, 8, micro::stg_x8),
+    Micro::latency("st2g", 1, micro::st2g_latency),
+    Micro::unrolled("st2g", 32, 1, micro::st2g_x1),
+    Micro::unrolled("st2g", 32, 2, micro::st2g_x2),
+    Micro::unrolled("st2g", 32, 4, micro::st2g_x4),
+    Micro::unrolled("st2g", 32, 8, micro::st2g_x8),
+    Micro::latency("stzg", 1, micro::stzg_latency),
+    Micro::unrolled("stzg", 16, 1, micro::stzg_x1),
+    Micro::unrolled("stzg", 16, 2, micro::stzg_x2),
+    Micro::unrolled("stzg", 16, 4, micro::stzg_x4),
+    Micro::unrolled("stzg", 16, 8, micro::stzg_x8),
+    Micro::latency("stz2g", 1, micro::stz2g_latency),
+    Micro::unrolled("stz2g", 32, 1, micro::stz2g_x1),
+    Micro::unrolled("stz2g", 32, 2, micro::stz2g_x2),
+    Micro::unrolled("stz2g", 32, 4, micro::stz2g_x4),
+    Micro::unrolled("stz2g", 32, 8, micro::stz2g_x8),
+    Micro::latency("stgp", 1, micro::stgp_latency),
+    Micro::unrolled("stgp", 16, 1, micro::stgp_x1),
+    Micro::unrolled("stgp", 16, 2, micro::stgp_x2),
+    Micro::unrolled("stgp", 16, 4, micro::stgp_x4),
+    Micro::unrolled("stgp", 16, 8, micro::stgp_x8),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "unknown CPU feature")]
+    fn unknown_features_panic() {
+        detected("sve");
+    }
+
+    #[test]
+    fn mte_is_a_known_feature() {
+        let detected = detected("mte");
+        if cfg!(not(target_arch = "aarch64")) {
+            assert!(!detected);
+        }
+    }
+
+    // `VARIANTS` and `MICRO` pull in the tagging kernels, which only assemble for aarch64
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn variants_are_consistent() {
+        for (i, variant) in VARIANTS.iter().enumerate() {
+            // Panics on features `detected` does not know
+            for feature in variant.features {
+                detected(feature);
+            }
+            assert_eq!(
+                variant.instruction.is_some(),
+                variant.granule.is_some(),
+                "{}",
+                variant.name
+            );
+            assert!(
+                variant.instruction.is_some() || !variant.implicit_zero,
+                "{}",
+                variant.name
+            );
+            assert!(
+                VARIANTS[..i].iter().all(|v| v.name != variant.name),
+                "{} is not unique",
+                variant.name
+            );
+            assert!(std::ptr::eq(find(variant.name).unwrap(), variant));
+        }
+        assert!(find("dc gva").is_none());
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn micro_benchmarks_time_mte_instructions() {
+        const MTE: [&str; 10] = [
+            "irg", "addg", "subg", "gmi", "ldg", "stg", "st2g", "stzg", "stz2g", "stgp",
+        ];
+
+        for (i, micro) in MICRO.iter().enumerate() {
+            assert!(MTE.contains(&micro.instruction), "{}", micro.name());
+            assert!(micro.ops > 0, "{}", micro.name());
+            assert!(
+                MICRO[..i].iter().all(|m| m.name() != micro.name()),
+                "{} is not unique",
+                micro.name()
+            );
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn supported_variants_pass_validation() {
+        use crate::region::{AllocPolicy, PagePolicy, Region};
+        use crate::{set_mte_mode, MTEMode};
+
+        if !detected("mte") {
+            assert_eq!(
+                supported().count(),
+                VARIANTS.iter().filter(|v| v.features.is_empty()).count()
+            );
+            return;
+        }
+
+        unsafe { set_mte_mode(MTEMode::Sync) };
+        let mut region = Region::prepare(4096, AllocPolicy::Populate, PagePolicy::Default)
+            .expect("could not map memory");
+        let mem = region.as_mut_slice();
+
+        for variant in supported() {
+            unsafe {
+                // Untagged, so that `memset` can write it
+                stg(mem, 0);
+                (variant.run)(mem, 0x0300_0000_0000_0000);
+                variant.validate(mem, 0x0300_0000_0000_0000).unwrap();
+            }
+        }
+
+        let memset = find("memset").unwrap();
+        unsafe {
+            stg(mem, 0);
+            (memset.run)(mem, 0);
+            mem[100] = 1;
+            let err = memset.validate(mem, 0).unwrap_err();
+            assert!(
+                err.contains("did not zero the granule at offset 96"),
+                "{}",
+                err
+            );
+
+            let err = find("stg")
+                .unwrap()
+                .validate(mem, 0x0300_0000_0000_0000)
+                .unwrap_err();
+            assert!(
+                err.contains("left tag 0x0 instead of 0x3 at offset 0"),
+                "{}",
+                err
+            );
+        }
+    }
+}